@@ -0,0 +1,469 @@
+use std::collections::HashSet;
+use std::marker::PhantomData;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context as _, Result as AHResult};
+use aws_lc_rs::{constant_time, digest, hmac};
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, FromRef, FromRequestParts, Request, State},
+    http::{HeaderMap, Method, StatusCode, Uri, header::WWW_AUTHENTICATE, request::Parts},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use axum_extra::TypedHeader;
+use headers::{Authorization, authorization::Bearer};
+use papaya::HashMap;
+use serde::Deserialize;
+
+use crate::listener::Peer;
+
+const QUOTA_WINDOW: Duration = Duration::from_secs(60);
+/// Room for Discord's 25 MiB of attachments once base64-encoded, plus the message.
+pub const SEND_BODY_LIMIT: usize = 40 * 1024 * 1024;
+const SIGNED_BODY_LIMIT: usize = SEND_BODY_LIMIT;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    Send,
+    NotfoundsRead,
+    NotfoundsDelete,
+    Admin,
+}
+
+pub trait RequiredScope {
+    const SCOPE: Scope;
+}
+
+pub struct SendScope;
+pub struct NotfoundsReadScope;
+pub struct NotfoundsDeleteScope;
+pub struct AdminScope;
+
+impl RequiredScope for SendScope {
+    const SCOPE: Scope = Scope::Send;
+}
+
+impl RequiredScope for NotfoundsReadScope {
+    const SCOPE: Scope = Scope::NotfoundsRead;
+}
+
+impl RequiredScope for NotfoundsDeleteScope {
+    const SCOPE: Scope = Scope::NotfoundsDelete;
+}
+
+impl RequiredScope for AdminScope {
+    const SCOPE: Scope = Scope::Admin;
+}
+
+#[derive(Debug)]
+pub enum Denied {
+    Unauthorized,
+    Forbidden,
+    QuotaExceeded,
+}
+
+impl IntoResponse for Denied {
+    fn into_response(self) -> Response {
+        match self {
+            Self::Unauthorized => (
+                StatusCode::UNAUTHORIZED,
+                [(WWW_AUTHENTICATE, r#"Bearer realm="webhook-sender""#)],
+                "UNAUTHORIZED",
+            )
+                .into_response(),
+            Self::Forbidden => (StatusCode::FORBIDDEN, "FORBIDDEN").into_response(),
+            Self::QuotaExceeded => {
+                (StatusCode::TOO_MANY_REQUESTS, "QUOTA_EXCEEDED").into_response()
+            }
+        }
+    }
+}
+
+type TokenHash = [u8; 32];
+
+fn hash(secret: &str) -> TokenHash {
+    digest::digest(&digest::SHA256, secret.as_bytes())
+        .as_ref()
+        .try_into()
+        .unwrap()
+}
+
+fn decode_hex<const N: usize>(hex: &str) -> Option<[u8; N]> {
+    if hex.len() != N * 2 {
+        return None;
+    }
+
+    let mut bytes = [0; N];
+
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+
+    Some(bytes)
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenEntry {
+    name: String,
+    token: Option<String>,
+    token_sha256: Option<String>,
+    key_id: Option<String>,
+    signing_secret: Option<String>,
+    scopes: HashSet<Scope>,
+    targets_per_minute: Option<usize>,
+}
+
+pub struct Token {
+    pub name: String,
+    hash: Option<TokenHash>,
+    signing: Option<(String, hmac::Key)>,
+    scopes: HashSet<Scope>,
+    targets_per_minute: Option<usize>,
+    window: Mutex<(Instant, usize)>,
+}
+
+impl Token {
+    fn new(entry: TokenEntry) -> AHResult<Self> {
+        let hash = match (&entry.token, &entry.token_sha256) {
+            (Some(token), None) => Some(hash(token)),
+            (None, Some(hex)) => Some(
+                decode_hex(hex)
+                    .with_context(|| format!("{} has a malformed token_sha256", entry.name))?,
+            ),
+            (None, None) => None,
+            _ => anyhow::bail!("{} has both token and token_sha256", entry.name),
+        };
+
+        let signing = match (entry.key_id, entry.signing_secret) {
+            (Some(key_id), Some(secret)) => {
+                Some((key_id, hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes())))
+            }
+            (None, None) => None,
+            _ => anyhow::bail!("{} needs both key_id and signing_secret", entry.name),
+        };
+
+        anyhow::ensure!(
+            hash.is_some() || signing.is_some(),
+            "{} has neither a token nor a signing key",
+            entry.name
+        );
+
+        Ok(Self {
+            name: entry.name,
+            hash,
+            signing,
+            scopes: entry.scopes,
+            targets_per_minute: entry.targets_per_minute,
+            window: Mutex::new((Instant::now(), 0)),
+        })
+    }
+
+    /// Whether this token may act on other tokens' jobs.
+    pub fn is_admin(&self) -> bool {
+        self.scopes.contains(&Scope::Admin)
+    }
+
+    /// The issuer this token is confined to, `None` for admins.
+    pub fn owner(&self) -> Option<&str> {
+        (!self.is_admin()).then_some(self.name.as_str())
+    }
+
+    fn permits(&self, scope: Scope) -> Result<(), Denied> {
+        if self.scopes.contains(&scope) || self.scopes.contains(&Scope::Admin) {
+            Ok(())
+        } else {
+            Err(Denied::Forbidden)
+        }
+    }
+
+    /// Counts `targets` against the per-minute quota, refusing if it would be exceeded.
+    pub fn consume(&self, targets: usize) -> Result<(), Denied> {
+        let Some(limit) = self.targets_per_minute else {
+            return Ok(());
+        };
+
+        let mut window = self.window.lock().unwrap();
+        let (started_at, used) = &mut *window;
+
+        if started_at.elapsed() >= QUOTA_WINDOW {
+            *started_at = Instant::now();
+            *used = 0;
+        }
+
+        if *used + targets > limit {
+            return Err(Denied::QuotaExceeded);
+        }
+
+        *used += targets;
+
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Token")
+            .field("name", &self.name)
+            .field("scopes", &self.scopes)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug)]
+pub struct Tokens {
+    tokens: Vec<Token>,
+    signature_window: Duration,
+    seen_signatures: HashMap<[u8; 32], Instant>,
+}
+
+impl Tokens {
+    /// Loads the registry from a JSON array of
+    /// `{ name, token | token_sha256, key_id, signing_secret, scopes, targets_per_minute }`.
+    /// A bare `auth_token` is registered as `default` with every scope.
+    /// Only SHA-256 hashes of bearer tokens are kept.
+    pub fn load(
+        file: Option<&Path>,
+        auth_token: Option<String>,
+        signature_window: Duration,
+    ) -> AHResult<Self> {
+        let mut entries: Vec<TokenEntry> = match file {
+            Some(file) => {
+                let json = std::fs::read(file).context("Failed to read tokens file")?;
+                serde_json::from_slice(&json).context("Failed to parse tokens file")?
+            }
+            None => vec![],
+        };
+
+        if let Some(auth_token) = auth_token {
+            entries.push(TokenEntry {
+                name: "default".to_string(),
+                token: Some(auth_token),
+                token_sha256: None,
+                key_id: None,
+                signing_secret: None,
+                scopes: HashSet::from([Scope::Admin]),
+                targets_per_minute: None,
+            });
+        }
+
+        anyhow::ensure!(!entries.is_empty(), "No API token is configured");
+
+        // Names scope idempotency keys and jobs, and key IDs pick the signing secret.
+        let mut names = HashSet::new();
+        let mut key_ids = HashSet::new();
+
+        for entry in &entries {
+            anyhow::ensure!(
+                names.insert(entry.name.as_str()),
+                "Token name {:?} is used more than once",
+                entry.name
+            );
+
+            if let Some(key_id) = &entry.key_id {
+                anyhow::ensure!(
+                    key_ids.insert(key_id.as_str()),
+                    "Key ID {key_id:?} is used more than once"
+                );
+            }
+        }
+
+        Ok(Self {
+            tokens: entries
+                .into_iter()
+                .map(Token::new)
+                .collect::<AHResult<_>>()?,
+            signature_window,
+            seen_signatures: HashMap::default(),
+        })
+    }
+
+    pub fn authorize(&self, secret: &str, scope: Scope) -> Result<&Token, Denied> {
+        let presented = hash(secret);
+
+        // Compare against every token so the timing does not reveal which one matched.
+        let token = self.tokens.iter().fold(None, |found, token| {
+            let matched = token.hash.is_some_and(|hash| {
+                constant_time::verify_slices_are_equal(&hash, &presented).is_ok()
+            });
+            if matched { Some(token) } else { found }
+        });
+
+        let token = token.ok_or(Denied::Unauthorized)?;
+        token.permits(scope)?;
+
+        Ok(token)
+    }
+
+    /// Checks `X-Signature-256: sha256=HMAC(secret, "{timestamp}.{method}.{path}.{body}")`
+    /// for `X-Key-Id`, with the timestamp from `X-Signature-Timestamp` as on outgoing callbacks.
+    /// Refuses stale timestamps and signatures already seen within the window.
+    fn verify_signature(
+        &self,
+        method: &Method,
+        uri: &Uri,
+        headers: &HeaderMap,
+        body: &[u8],
+    ) -> Result<&Token, Denied> {
+        let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+
+        let key_id = header("x-key-id").ok_or(Denied::Unauthorized)?;
+        let timestamp = header("x-signature-timestamp").ok_or(Denied::Unauthorized)?;
+        let signature: [u8; 32] = header("x-signature-256")
+            .and_then(|signature| signature.strip_prefix("sha256="))
+            .and_then(decode_hex)
+            .ok_or(Denied::Unauthorized)?;
+
+        let signed_at = timestamp
+            .parse()
+            .ok()
+            .and_then(|secs| UNIX_EPOCH.checked_add(Duration::from_secs(secs)))
+            .ok_or(Denied::Unauthorized)?;
+
+        let skew = match signed_at.duration_since(SystemTime::now()) {
+            Ok(ahead) => ahead,
+            Err(e) => e.duration(),
+        };
+
+        if skew > self.signature_window {
+            return Err(Denied::Unauthorized);
+        }
+
+        let (token, key) = self
+            .tokens
+            .iter()
+            .find_map(|token| match &token.signing {
+                Some((id, key)) if id == key_id => Some((token, key)),
+                _ => None,
+            })
+            .ok_or(Denied::Unauthorized)?;
+
+        let path = uri
+            .path_and_query()
+            .map_or(uri.path(), |path| path.as_str());
+        let prefix = format!("{timestamp}.{method}.{path}.");
+
+        let mut message = Vec::with_capacity(prefix.len() + body.len());
+        message.extend_from_slice(prefix.as_bytes());
+        message.extend_from_slice(body);
+
+        hmac::verify(key, &message, &signature).map_err(|_| Denied::Unauthorized)?;
+
+        let expires_at = Instant::now() + self.signature_window * 2;
+
+        if self
+            .seen_signatures
+            .pin()
+            .try_insert(signature, expires_at)
+            .is_err()
+        {
+            return Err(Denied::Unauthorized);
+        }
+
+        Ok(token)
+    }
+
+    pub fn purge(&self) {
+        let now = Instant::now();
+        self.seen_signatures
+            .pin()
+            .retain(|_, expires| *expires > now);
+    }
+}
+
+/// A token proven by a request signature, left for [`Authorized`] to pick up.
+#[derive(Clone, Copy)]
+struct Signed(&'static Token);
+
+/// Verifies HMAC-signed requests (those carrying `X-Key-Id`); others fall through to bearer auth.
+pub async fn verify_signed(
+    State(tokens): State<&'static Tokens>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !request.headers().contains_key("x-key-id") {
+        return next.run(request).await;
+    }
+
+    let (parts, body) = request.into_parts();
+
+    let Ok(body) = axum::body::to_bytes(body, SIGNED_BODY_LIMIT).await else {
+        return (StatusCode::PAYLOAD_TOO_LARGE, "PAYLOAD_TOO_LARGE").into_response();
+    };
+
+    let token = match tokens.verify_signature(&parts.method, &parts.uri, &parts.headers, &body) {
+        Ok(token) => token,
+        Err(denied) => {
+            let client = parts
+                .extensions
+                .get::<ConnectInfo<Peer>>()
+                .map(|ConnectInfo(addr)| addr.to_string())
+                .unwrap_or_else(|| "unknown".to_string());
+
+            tracing::warn!("{client} {} Signature rejected", parts.uri.path());
+            return denied.into_response();
+        }
+    };
+
+    let mut request = Request::from_parts(parts, Body::from(body));
+    request.extensions_mut().insert(Signed(token));
+
+    next.run(request).await
+}
+
+/// The caller's token, checked to carry scope `S`.
+pub struct Authorized<S> {
+    pub token: &'static Token,
+    scope: PhantomData<S>,
+}
+
+impl<S, St> FromRequestParts<St> for Authorized<S>
+where
+    S: RequiredScope,
+    St: Send + Sync,
+    &'static Tokens: FromRef<St>,
+{
+    type Rejection = Denied;
+
+    async fn from_request_parts(parts: &mut Parts, state: &St) -> Result<Self, Self::Rejection> {
+        let tokens = <&'static Tokens>::from_ref(state);
+
+        if let Some(Signed(token)) = parts.extensions.get::<Signed>().copied() {
+            token.permits(S::SCOPE)?;
+
+            return Ok(Self {
+                token,
+                scope: PhantomData,
+            });
+        }
+
+        let client = parts
+            .extensions
+            .get::<ConnectInfo<Peer>>()
+            .map(|ConnectInfo(addr)| addr.to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+
+        let path = parts.uri.path().to_owned();
+
+        let bearer = TypedHeader::<Authorization<Bearer>>::from_request_parts(parts, state).await;
+
+        let result = match &bearer {
+            Ok(TypedHeader(bearer)) => tokens.authorize(bearer.token(), S::SCOPE),
+            Err(_) => Err(Denied::Unauthorized),
+        };
+
+        match result {
+            Ok(token) => Ok(Self {
+                token,
+                scope: PhantomData,
+            }),
+            Err(denied) => {
+                tracing::warn!("{client} {path} Authentication failed ({denied:?})");
+                Err(denied)
+            }
+        }
+    }
+}
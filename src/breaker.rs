@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+use std::net::SocketAddrV4;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use serde::Serialize;
+
+const BASE_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// Consecutive failures after which the pair is marked unhealthy.
+const TRIP_THRESHOLD: usize = 5;
+/// How long an unhealthy pair rests before a single probe is let through.
+const OPEN_DURATION: Duration = Duration::from_secs(120);
+
+/// A source address and the Discord edge it connects to.
+type Pair = (SocketAddrV4, SocketAddrV4);
+
+static BREAKERS: Lazy<Mutex<HashMap<Pair, Arc<Breaker>>>> = Lazy::new(Mutex::default);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakerState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+#[derive(Debug)]
+struct State {
+    state: BreakerState,
+    failures: usize,
+    retry_at: Option<Instant>,
+}
+
+/// Reconnect backoff and circuit breaker shared by every slot connecting `from` to `to`.
+#[derive(Debug)]
+pub struct Breaker {
+    state: Mutex<State>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct BreakerStats {
+    pub state: BreakerState,
+    pub consecutive_failures: usize,
+    pub retry_in_secs: Option<f64>,
+}
+
+pub fn get(from: SocketAddrV4, to: SocketAddrV4) -> Arc<Breaker> {
+    BREAKERS
+        .lock()
+        .unwrap()
+        .entry((from, to))
+        .or_insert_with(|| {
+            Arc::new(Breaker {
+                state: Mutex::new(State {
+                    state: BreakerState::Closed,
+                    failures: 0,
+                    retry_at: None,
+                }),
+            })
+        })
+        .clone()
+}
+
+impl Breaker {
+    /// How long to wait before connecting. Zero grants the attempt;
+    /// while half-open only the first caller gets to probe.
+    pub fn wait(&self) -> Duration {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+
+        if let Some(retry_at) = state.retry_at
+            && retry_at > now
+        {
+            return retry_at - now;
+        }
+
+        match state.state {
+            BreakerState::Closed => Duration::ZERO,
+            BreakerState::Open => {
+                state.state = BreakerState::HalfOpen;
+                state.retry_at = None;
+                Duration::ZERO
+            }
+            // A probe is in flight; check back later.
+            BreakerState::HalfOpen => BASE_BACKOFF,
+        }
+    }
+
+    pub fn succeeded(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.state = BreakerState::Closed;
+        state.failures = 0;
+        state.retry_at = None;
+    }
+
+    /// Records a failed connection and returns whether it tripped the breaker.
+    pub fn failed(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.failures += 1;
+
+        if state.state == BreakerState::HalfOpen || state.failures >= TRIP_THRESHOLD {
+            let tripped = state.state != BreakerState::Open;
+            state.state = BreakerState::Open;
+            state.retry_at = Some(Instant::now() + OPEN_DURATION);
+            return tripped;
+        }
+
+        let backoff = BASE_BACKOFF
+            .saturating_mul(1 << (state.failures - 1).min(16))
+            .min(MAX_BACKOFF);
+
+        // Jitter keeps slots sharing a pair from reconnecting in lockstep.
+        let backoff = backoff.mul_f64(rand::random_range(0.5..1.0));
+
+        // Another slot may have pushed it further out already.
+        let retry_at = Instant::now() + backoff;
+        state.retry_at = Some(state.retry_at.map_or(retry_at, |at| at.max(retry_at)));
+
+        false
+    }
+
+    pub fn stats(&self) -> BreakerStats {
+        let state = self.state.lock().unwrap();
+
+        BreakerStats {
+            state: state.state,
+            consecutive_failures: state.failures,
+            retry_in_secs: state
+                .retry_at
+                .map(|at| at.saturating_duration_since(Instant::now()).as_secs_f64())
+                .filter(|secs| *secs > 0.0),
+        }
+    }
+}
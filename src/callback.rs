@@ -0,0 +1,378 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context as _, Result as AHResult};
+use aws_lc_rs::hmac;
+use bytes::Bytes;
+use http::{
+    Request, StatusCode,
+    header::{CONTENT_TYPE, HOST, USER_AGENT},
+};
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::client::conn::http1::SendRequest;
+use hyper_util::rt::TokioIo;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+};
+use tokio_rustls::{
+    TlsConnector,
+    rustls::{RootCertStore, pki_types::ServerName},
+};
+
+use crate::events::Event;
+
+const CALLBACK_WORKERS: usize = 4;
+/// Workers that only post `finished`, so per-target events can't hold them up.
+const FINISHED_WORKERS: usize = 2;
+/// Deliveries waiting per queue; events past this are dropped.
+const CALLBACK_QUEUE_LIMIT: usize = 10_000;
+/// Idle keep-alive connections kept per callback origin.
+const IDLE_CONNECTIONS_PER_ORIGIN: usize = 4;
+/// Responses are read only to free the connection for reuse.
+const RESPONSE_BODY_LIMIT: usize = 64 * 1024;
+const CALLBACK_RETRY_LIMIT: u32 = 5;
+const CALLBACK_TIMEOUT: Duration = Duration::from_secs(10);
+/// Deliveries in flight per origin, so one slow endpoint can't occupy every worker.
+const MAX_IN_FLIGHT_PER_ORIGIN: usize = 2;
+/// How long a delivery to a busy origin waits before going back on the queue.
+const BUSY_ORIGIN_DELAY: Duration = Duration::from_millis(500);
+
+#[derive(Clone, Debug)]
+pub struct Callback {
+    pub url: url::Url,
+    pub events: bool,
+    pub notifier: &'static Notifier,
+}
+
+struct Delivery {
+    url: url::Url,
+    body: Bytes,
+    finished: bool,
+    attempt: u32,
+}
+
+/// Scheme, host and port of a callback URL.
+type Origin = (String, String, u16);
+
+/// Posts job events to caller-supplied URLs, on its own connections and workers.
+pub struct Notifier {
+    key: hmac::Key,
+    tls: TlsConnector,
+    tx: async_channel::Sender<Delivery>,
+    finished_tx: async_channel::Sender<Delivery>,
+    idle: Mutex<HashMap<Origin, Vec<SendRequest<Full<Bytes>>>>>,
+    in_flight: Mutex<HashMap<Origin, usize>>,
+}
+
+impl std::fmt::Debug for Notifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Notifier").finish_non_exhaustive()
+    }
+}
+
+impl Notifier {
+    pub fn spawn(secret: &str) -> &'static Self {
+        let tls_client_config = Arc::new({
+            let root_store =
+                RootCertStore::from_iter(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+
+            let mut c = tokio_rustls::rustls::ClientConfig::builder()
+                .with_root_certificates(root_store)
+                .with_no_client_auth();
+
+            c.alpn_protocols.push(b"http/1.1".to_vec());
+
+            c
+        });
+
+        let (tx, rx) = async_channel::bounded(CALLBACK_QUEUE_LIMIT);
+        let (finished_tx, finished_rx) = async_channel::bounded(CALLBACK_QUEUE_LIMIT);
+
+        let notifier = &*Box::leak(Box::new(Self {
+            key: hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes()),
+            tls: TlsConnector::from(tls_client_config),
+            tx,
+            finished_tx,
+            idle: Mutex::default(),
+            in_flight: Mutex::default(),
+        }));
+
+        let workers = std::iter::repeat_n(rx, CALLBACK_WORKERS)
+            .chain(std::iter::repeat_n(finished_rx, FINISHED_WORKERS));
+
+        for rx in workers {
+            tokio::spawn(async move {
+                while let Ok(delivery) = rx.recv().await {
+                    notifier.deliver(delivery).await;
+                }
+            });
+        }
+
+        notifier
+    }
+
+    pub fn notify(&self, url: &url::Url, event: &Event) {
+        let body = Bytes::from(serde_json::to_vec(event).unwrap());
+
+        let delivery = Delivery {
+            url: url.clone(),
+            body,
+            finished: matches!(event, Event::Finished { .. }),
+            attempt: 0,
+        };
+
+        self.enqueue(delivery);
+    }
+
+    fn enqueue(&self, delivery: Delivery) {
+        let tx = if delivery.finished {
+            &self.finished_tx
+        } else {
+            &self.tx
+        };
+
+        if let Err(e) = tx.try_send(delivery) {
+            let url = &e.into_inner().url;
+            tracing::warn!("Callback queue is full. Dropped an event for {url}.");
+        }
+    }
+
+    /// Puts the delivery back on its queue after `delay`, leaving the worker free meanwhile.
+    fn requeue(&'static self, delivery: Delivery, delay: Duration) {
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            self.enqueue(delivery);
+        });
+    }
+
+    /// Makes one attempt; retries are scheduled back onto the queue.
+    async fn deliver(&'static self, mut delivery: Delivery) {
+        let url = &delivery.url;
+
+        let Ok(origin) = origin(url) else {
+            tracing::warn!("Callback to {url} has no usable origin. Canceled.");
+            return;
+        };
+
+        {
+            let mut in_flight = self.in_flight.lock().unwrap();
+            let count = in_flight.entry(origin.clone()).or_default();
+
+            if *count >= MAX_IN_FLIGHT_PER_ORIGIN {
+                drop(in_flight);
+                self.requeue(delivery, BUSY_ORIGIN_DELAY);
+                return;
+            }
+
+            *count += 1;
+        }
+
+        let result = tokio::time::timeout(CALLBACK_TIMEOUT, self.post(url, &delivery.body))
+            .await
+            .context("Timed out");
+
+        {
+            let mut in_flight = self.in_flight.lock().unwrap();
+
+            if let Some(count) = in_flight.get_mut(&origin) {
+                *count -= 1;
+
+                if *count == 0 {
+                    in_flight.remove(&origin);
+                }
+            }
+        }
+
+        match result {
+            Ok(Ok(status)) if status.is_success() => return,
+            Ok(Ok(status))
+                if status.is_client_error() && status != StatusCode::TOO_MANY_REQUESTS =>
+            {
+                tracing::warn!("Callback to {url} rejected with {status}. Canceled.");
+                return;
+            }
+            Ok(Ok(status)) => tracing::warn!("Callback to {url} got {status}. Retrying..."),
+            Ok(Err(e)) | Err(e) => {
+                tracing::warn!("Callback to {url} failed {e:?}. Retrying...")
+            }
+        }
+
+        delivery.attempt += 1;
+
+        if delivery.attempt >= CALLBACK_RETRY_LIMIT {
+            tracing::warn!("Callback to {url} retry limit reached. Canceled.");
+            return;
+        }
+
+        let backoff = Duration::from_secs(1 << (delivery.attempt - 1));
+        self.requeue(delivery, backoff);
+    }
+
+    async fn post(&self, url: &url::Url, body: &Bytes) -> AHResult<StatusCode> {
+        let origin = origin(url)?;
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            .to_string();
+
+        let signature = {
+            let mut ctx = hmac::Context::with_key(&self.key);
+            ctx.update(timestamp.as_bytes());
+            ctx.update(b".");
+            ctx.update(body);
+            ctx.sign()
+        };
+
+        let signature: String = signature
+            .as_ref()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+
+        let path = &url[url::Position::BeforePath..url::Position::AfterQuery];
+        let authority = &url[url::Position::BeforeHost..url::Position::AfterPort];
+
+        let request = Request::post(path)
+            .header(HOST, authority)
+            .header(CONTENT_TYPE, "application/json")
+            .header(USER_AGENT, "WebhookSender/0.1.0")
+            .header("X-Signature-Timestamp", &timestamp)
+            .header("X-Signature-256", format!("sha256={signature}"))
+            .body(Full::new(body.clone()))
+            .unwrap();
+
+        let mut sender = match self.checkout(&origin) {
+            Some(sender) => sender,
+            None => self.connect(&origin).await?,
+        };
+
+        let response = sender.send_request(request).await?;
+        let status = response.status();
+
+        // The connection is only reusable once the response is read in full.
+        if Limited::new(response.into_body(), RESPONSE_BODY_LIMIT)
+            .collect()
+            .await
+            .is_ok()
+        {
+            self.checkin(origin, sender);
+        }
+
+        Ok(status)
+    }
+
+    fn checkout(&self, origin: &Origin) -> Option<SendRequest<Full<Bytes>>> {
+        let mut idle = self.idle.lock().unwrap();
+        let senders = idle.get_mut(origin)?;
+
+        while let Some(sender) = senders.pop() {
+            if sender.is_ready() {
+                return Some(sender);
+            }
+        }
+
+        None
+    }
+
+    fn checkin(&self, origin: Origin, sender: SendRequest<Full<Bytes>>) {
+        let mut idle = self.idle.lock().unwrap();
+        let senders = idle.entry(origin).or_default();
+
+        senders.retain(|sender| !sender.is_closed());
+
+        if senders.len() < IDLE_CONNECTIONS_PER_ORIGIN {
+            senders.push(sender);
+        }
+    }
+
+    async fn connect(&self, (scheme, host, port): &Origin) -> AHResult<SendRequest<Full<Bytes>>> {
+        // Connecting to the vetted address keeps DNS rebinding from getting around the check.
+        let addr = tokio::net::lookup_host((host.as_str(), *port))
+            .await
+            .context("Failed to resolve callback host")?
+            .find(|addr: &SocketAddr| is_public(addr.ip()))
+            .context("Callback host has no public address")?;
+
+        let tcp_stream = TcpStream::connect(addr)
+            .await
+            .context("Failed to establish TCP connection")?;
+
+        match scheme.as_str() {
+            "https" => {
+                let dns_name = ServerName::try_from(host.to_owned())?;
+                let tls = self.tls.connect(dns_name, tcp_stream).await?;
+                handshake(tls).await
+            }
+            _ => handshake(tcp_stream).await,
+        }
+    }
+}
+
+/// `host_str` keeps the brackets of an IPv6 literal, which neither DNS nor SNI accept.
+fn origin(url: &url::Url) -> AHResult<Origin> {
+    let host = match url.host().context("Callback URL has no host")? {
+        url::Host::Ipv6(ip) => ip.to_string(),
+        host => host.to_string(),
+    };
+
+    let port = url
+        .port_or_known_default()
+        .context("Callback URL has no port")?;
+
+    Ok((url.scheme().to_owned(), host, port))
+}
+
+async fn handshake<T>(io: T) -> AHResult<SendRequest<Full<Bytes>>>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(io)).await?;
+
+    tokio::spawn(async move {
+        // The error surfaces through send_request.
+        let _ = connection.await;
+    });
+
+    Ok(sender)
+}
+
+/// Whether `ip` is on the public internet, so callbacks can't reach internal services.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip.to_canonical() {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            // 0.0.0.0/8 and the 100.64.0.0/10 shared address space.
+            let reserved = a == 0 || (a == 100 && b & 0xc0 == 64);
+
+            !(reserved
+                || ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation())
+        }
+        IpAddr::V6(ip) => {
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_unique_local()
+                || ip.is_unicast_link_local()
+                || ip.is_multicast())
+        }
+    }
+}
+
+/// Rejects callback URLs naming a non-public address outright; hostnames are checked on delivery.
+pub fn is_allowed(url: &url::Url) -> bool {
+    match url.host() {
+        Some(url::Host::Ipv4(ip)) => is_public(ip.into()),
+        Some(url::Host::Ipv6(ip)) => is_public(ip.into()),
+        Some(url::Host::Domain(domain)) => domain != "localhost",
+        None => false,
+    }
+}
@@ -0,0 +1,188 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Used until the server's SETTINGS_MAX_CONCURRENT_STREAMS is known.
+const DEFAULT_MAX_CONCURRENT_STREAMS: usize = 98;
+/// Streams left unused below the server's limit.
+const STREAM_HEADROOM: usize = 2;
+const MIN_LIMIT: f64 = 1.0;
+const DECREASE_FACTOR: f64 = 0.5;
+/// At most one decrease per this period, so a burst of refusals counts once.
+const DECREASE_COOLDOWN: Duration = Duration::from_secs(1);
+/// Smoothed latency this many times its lowest value on the connection counts as elevated...
+const LATENCY_RISE_FACTOR: f64 = 2.0;
+/// ...and only signals congestion once it has stayed elevated this long.
+const LATENCY_RISE_SUSTAIN: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+struct State {
+    limit: f64,
+    ceiling: usize,
+    /// Permits in existence, in use or not.
+    issued: usize,
+    last_decrease: Option<Instant>,
+    baseline_latency: Option<Duration>,
+    elevated_since: Option<Instant>,
+}
+
+/// Stream permits for one HTTP/2 connection, sized AIMD-style:
+/// +1 per window of successful responses, halved on refused or reset streams and on a
+/// sustained rise of the smoothed latency, capped by the server's SETTINGS.
+/// Single slow responses are normal for Discord and don't count.
+#[derive(Debug)]
+pub struct Concurrency {
+    semaphore: Arc<Semaphore>,
+    state: Mutex<State>,
+}
+
+/// A stream slot. Dropped permits above the current limit are retired instead of returned.
+#[derive(Debug)]
+pub struct Permit {
+    permit: Option<OwnedSemaphorePermit>,
+    concurrency: Arc<Concurrency>,
+}
+
+impl Permit {
+    pub fn concurrency(&self) -> &Concurrency {
+        &self.concurrency
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let Some(permit) = self.permit.take() else {
+            return;
+        };
+
+        let mut state = self.concurrency.state.lock().unwrap();
+
+        if state.issued > state.limit as usize {
+            permit.forget();
+            state.issued -= 1;
+        }
+    }
+}
+
+impl Concurrency {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            semaphore: Arc::new(Semaphore::new(DEFAULT_MAX_CONCURRENT_STREAMS)),
+            state: Mutex::new(State {
+                limit: DEFAULT_MAX_CONCURRENT_STREAMS as f64,
+                ceiling: DEFAULT_MAX_CONCURRENT_STREAMS,
+                issued: DEFAULT_MAX_CONCURRENT_STREAMS,
+                last_decrease: None,
+                baseline_latency: None,
+                elevated_since: None,
+            }),
+        })
+    }
+
+    /// `None` once the connection is closed to new streams.
+    pub async fn acquire(self: &Arc<Self>) -> Option<Permit> {
+        let permit = self.semaphore.clone().acquire_owned().await.ok()?;
+
+        Some(Permit {
+            permit: Some(permit),
+            concurrency: self.clone(),
+        })
+    }
+
+    /// Admits no more streams, waking a sender waiting for a permit.
+    /// Returns whether this call was the one to close it.
+    pub fn close(&self) -> bool {
+        let _state = self.state.lock().unwrap();
+        let first = !self.semaphore.is_closed();
+        self.semaphore.close();
+        first
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.semaphore.is_closed()
+    }
+
+    /// Follows the server's SETTINGS_MAX_CONCURRENT_STREAMS; `usize::MAX` means not received yet.
+    pub fn set_server_max(&self, server_max: usize) {
+        let ceiling = match server_max {
+            usize::MAX => DEFAULT_MAX_CONCURRENT_STREAMS,
+            max => max.saturating_sub(STREAM_HEADROOM).max(MIN_LIMIT as usize),
+        };
+
+        let mut state = self.state.lock().unwrap();
+
+        if state.ceiling == ceiling {
+            return;
+        }
+
+        // A raised ceiling is grown into; a lowered one applies at once.
+        state.ceiling = ceiling;
+        state.limit = state.limit.min(ceiling as f64);
+        self.resize(&mut state);
+    }
+
+    /// Takes the connection's smoothed latency after a response.
+    pub fn responded(&self, smoothed: Duration) {
+        let mut state = self.state.lock().unwrap();
+
+        let baseline = state
+            .baseline_latency
+            .map_or(smoothed, |min| min.min(smoothed));
+        state.baseline_latency = Some(baseline);
+
+        if smoothed.as_secs_f64() <= baseline.as_secs_f64() * LATENCY_RISE_FACTOR {
+            state.elevated_since = None;
+            state.limit = (state.limit + 1.0 / state.limit).min(state.ceiling as f64);
+            self.resize(&mut state);
+            return;
+        }
+
+        let elevated_since = *state.elevated_since.get_or_insert_with(Instant::now);
+
+        if elevated_since.elapsed() >= LATENCY_RISE_SUSTAIN {
+            // Another full period must pass before the next latency-driven decrease.
+            state.elevated_since = None;
+            self.decrease(&mut state);
+        }
+    }
+
+    /// The server refused or reset a stream.
+    pub fn congested(&self) {
+        let mut state = self.state.lock().unwrap();
+        self.decrease(&mut state);
+    }
+
+    fn decrease(&self, state: &mut State) {
+        if state
+            .last_decrease
+            .is_some_and(|at| at.elapsed() < DECREASE_COOLDOWN)
+        {
+            return;
+        }
+
+        state.limit = (state.limit * DECREASE_FACTOR).max(MIN_LIMIT);
+        state.last_decrease = Some(Instant::now());
+        self.resize(state);
+    }
+
+    pub fn limit(&self) -> usize {
+        self.state.lock().unwrap().limit as usize
+    }
+
+    pub fn available(&self) -> usize {
+        self.semaphore.available_permits()
+    }
+
+    fn resize(&self, state: &mut State) {
+        let target = state.limit as usize;
+
+        if target > state.issued {
+            self.semaphore.add_permits(target - state.issued);
+            state.issued = target;
+        } else if target < state.issued {
+            // Permits in use are retired as they come back.
+            state.issued -= self.semaphore.forget_permits(state.issued - target);
+        }
+    }
+}
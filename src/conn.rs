@@ -1,10 +1,13 @@
 use std::net::{SocketAddr, SocketAddrV4};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use anyhow::{Context, Result as AHResult};
 use bytes::Bytes;
-use h2::client::{Connection, ResponseFuture, SendRequest};
+use h2::{
+    PingPong, Reason,
+    client::{Connection, ResponseFuture, SendRequest},
+};
 use http::{
     Request, StatusCode,
     header::{CONTENT_TYPE, HOST, HeaderMap, USER_AGENT},
@@ -12,7 +15,7 @@ use http::{
 };
 use tokio::{
     net::{TcpSocket, TcpStream},
-    sync::{OwnedSemaphorePermit, Semaphore},
+    time::MissedTickBehavior,
 };
 use tokio_rustls::{
     TlsConnector,
@@ -20,13 +23,20 @@ use tokio_rustls::{
     rustls::{RootCertStore, pki_types::ServerName},
 };
 
+use crate::concurrency::{Concurrency, Permit};
 use crate::discord::Ratelimit;
+use crate::events::{ConnectionState, Event};
 use crate::limiter::{Limiter, Status};
-use crate::request::{JobReceiver, JobSender};
+use crate::registry::{Pool, Slot};
+use crate::request::{JobReceiver, JobSender, Outcome};
 
 const ALPN_H2: &str = "h2";
-const HTTP2_SETTINGS_MAX_CONCURRENT_STREAMS: usize = 98;
-const CLOUDFLARE_HTTP2_REQUEST_LIMIT: usize = 9990;
+pub const CLOUDFLARE_HTTP2_REQUEST_LIMIT: usize = 9990;
+/// Requests before the limit at which a replacement connection starts being set up.
+const CONNECTION_ROTATION_MARGIN: usize = 200;
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+/// How long a replacement connection gets to answer a ping before the handover.
+const REPLACEMENT_PING_TIMEOUT: Duration = Duration::from_secs(5);
 
 async fn setup_connection(
     from: SocketAddrV4,
@@ -73,32 +83,57 @@ async fn setup_connection(
     Ok(h2::client::handshake(tls).await?)
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn response_handling(
     name: &str,
     request: crate::request::Request,
     response: ResponseFuture,
-    permit: OwnedSemaphorePermit,
+    permit: Permit,
     retry_tx: JobSender,
     limiter: &'static Limiter,
+    slot: Arc<Slot>,
+    sent_at: Instant,
 ) -> AHResult<()> {
     let mut response = match response.await {
         Ok(v) => v,
+        Err(e) if unprocessed(&e) => {
+            if e.is_go_away() {
+                if permit.concurrency().close() {
+                    slot.went_away(permit.concurrency(), close_reason(&e));
+                }
+            } else {
+                permit.concurrency().congested();
+            }
+
+            // The server never acted on it, so this isn't a retry.
+            retry_tx.send(request).await.unwrap();
+            return Err(e).context("Stream was not processed, requeued");
+        }
         Err(e) => {
+            if e.is_reset() && e.is_remote() {
+                permit.concurrency().congested();
+            }
+
             retry_tx.send(request.into_retry()).await.unwrap();
             return Err(e).context("Got error related to connection");
         }
     };
 
+    let smoothed = slot.responded(sent_at.elapsed());
+    permit.concurrency().responded(smoothed);
+
     let identity = &request.identity;
 
     match response.status() {
         status_code if status_code.is_success() => {
             tracing::debug!("{name} OK");
+            request.finish(Outcome::Delivered);
         }
 
         StatusCode::NOT_FOUND => {
-            limiter.tell_notfound(&request.target);
+            limiter.tell_notfound(&request.webhook);
             tracing::warn!("{name} {identity} 404 detected! Canceled.");
+            request.finish(Outcome::NotFound);
         }
 
         StatusCode::TOO_MANY_REQUESTS => {
@@ -114,15 +149,30 @@ async fn response_handling(
             };
 
             // The limiter may have a longer timeout.
-            let retry_after = limiter.tell_ratelimit(&request.target, retry_after);
+            let retry_after = limiter.tell_ratelimit(&request.webhook, retry_after);
 
             tracing::warn!(
                 "{name} {identity} Ratelimit Configured! (retry_after: {}s)",
                 retry_after.as_secs_f32()
             );
 
+            if crate::events::observed() {
+                crate::events::publish(Event::Ratelimited {
+                    job: request.context.job_id.clone(),
+                    identity: identity.clone(),
+                    webhook_id: request.webhook.id,
+                    retry_after: retry_after.as_secs_f32(),
+                });
+            }
+
             tokio::spawn(async move {
                 tokio::time::sleep(retry_after).await;
+
+                if request.context.is_cancelled() {
+                    request.finish(Outcome::Cancelled);
+                    return;
+                }
+
                 retry_tx.send(request.into_retry()).await.unwrap();
             });
         }
@@ -132,6 +182,7 @@ async fn response_handling(
                 "{name} {identity} {} Occured. Maybe invalid request. Canceled.",
                 status_code
             );
+            request.finish(Outcome::Rejected);
         }
 
         status_code if status_code.is_server_error() => {
@@ -144,6 +195,7 @@ async fn response_handling(
 
         status_code => {
             tracing::warn!("{name} {identity} Unknown StatusCode {}", status_code);
+            request.finish(Outcome::Unexpected);
         }
     }
 
@@ -152,6 +204,79 @@ async fn response_handling(
     Ok(())
 }
 
+/// Starts driving `connection`, so the server's PINGs and SETTINGS are answered
+/// even while it waits to be put into service.
+fn drive(
+    name: &'static str,
+    slot: &Arc<Slot>,
+    mut connection: Connection<TlsStream<TcpStream>>,
+) -> (PingPong, Arc<Concurrency>) {
+    let ping_pong = connection.ping_pong().unwrap();
+    let concurrency = Concurrency::new();
+
+    let driver_concurrency = concurrency.clone();
+    let driver_slot = slot.clone();
+
+    tokio::spawn(async move {
+        // Streams see the error through their own futures; this records why the connection ended.
+        let result = connection.await;
+        let first = driver_concurrency.close();
+
+        match result {
+            Ok(()) => tracing::debug!("{name} Connection closed"),
+            Err(e) if e.is_go_away() && e.is_remote() => {
+                tracing::warn!("{name} Connection closed by GOAWAY: {e}");
+
+                if first {
+                    driver_slot.went_away(&driver_concurrency, close_reason(&e));
+                }
+            }
+            Err(e) => {
+                tracing::warn!("{name} Connection failed: {e}");
+                driver_slot.closed(close_reason(&e));
+            }
+        }
+    });
+
+    (ping_pong, concurrency)
+}
+
+/// Puts a driven connection into service on `slot`.
+fn activate(name: &'static str, slot: &Slot, concurrency: &Arc<Concurrency>) {
+    tracing::info!("{name} Connection established!");
+
+    crate::events::publish(Event::Connection {
+        name,
+        state: ConnectionState::Open,
+    });
+
+    slot.opened(concurrency.clone());
+}
+
+/// Checks that a replacement connection survived its wait and still answers.
+async fn alive(ping_pong: &mut PingPong, concurrency: &Concurrency) -> AHResult<()> {
+    anyhow::ensure!(!concurrency.is_closed(), "Replacement connection closed");
+
+    tokio::time::timeout(REPLACEMENT_PING_TIMEOUT, ping_pong.ping(h2::Ping::opaque()))
+        .await
+        .context("Replacement connection did not answer ping")?
+        .context("Failed to ping replacement connection")?;
+
+    Ok(())
+}
+
+/// Refused streams and those above a GOAWAY's last-stream-id never reached the application.
+fn unprocessed(e: &h2::Error) -> bool {
+    e.is_remote() && (e.is_go_away() || e.reason() == Some(Reason::REFUSED_STREAM))
+}
+
+fn close_reason(e: &h2::Error) -> String {
+    match e.reason() {
+        Some(reason) => format!("{reason:?}"),
+        None => e.to_string(),
+    }
+}
+
 pub async fn sender(
     name: &'static str,
     from: SocketAddrV4,
@@ -159,31 +284,47 @@ pub async fn sender(
     request_rx: JobReceiver,
     retry_tx: JobSender,
     limiter: &'static Limiter,
+    slot: &Arc<Slot>,
 ) -> AHResult<()> {
-    let (mut client, mut connection) = setup_connection(from, to)
+    let (mut client, connection) = setup_connection(from, to)
         .await
         .context("Failed to connect to discord.com")?;
 
-    let mut ping_pong = connection.ping_pong().unwrap();
+    let (mut ping_pong, mut concurrency) = drive(name, slot, connection);
+    activate(name, slot, &concurrency);
 
-    tracing::info!("{name} Connection established!");
-
-    tokio::spawn(async move {
-        // The error handled by request sender and response handler.
-        connection.await.expect("Connection Failed");
-    });
+    let mut request_count = 0;
 
-    let semaphroe = Arc::new(Semaphore::new(HTTP2_SETTINGS_MAX_CONCURRENT_STREAMS));
+    // Set up in the background as the request limit nears, then swapped in at the limit.
+    let mut replacement = None;
 
-    let mut request_count = 0;
+    // Not reset by traffic, so busy connections get their RTT measured too.
+    let mut ping_interval = tokio::time::interval(PING_INTERVAL);
+    ping_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    ping_interval.reset();
 
     let mut headers = HeaderMap::new();
-    headers.insert(CONTENT_TYPE, "application/json".parse().unwrap());
     headers.insert(USER_AGENT, "WebhookSender/0.1.0".parse().unwrap());
     headers.insert(HOST, "discord.com".parse().unwrap());
 
     loop {
-        let permit = semaphroe.clone().acquire_owned().await.unwrap();
+        // SETTINGS may arrive or change at any point of the connection.
+        concurrency.set_server_max(client.current_max_send_streams());
+
+        let Some(permit) = concurrency.acquire().await else {
+            tracing::info!("{name} Connection is going away. Connection will be closed.");
+            return Ok(());
+        };
+
+        // With a backlog every slot should be busy; otherwise let faster edges go first.
+        if request_rx.is_empty() {
+            let handicap = slot.dispatch_handicap();
+
+            if !handicap.is_zero() {
+                tokio::time::sleep(handicap).await;
+            }
+        }
+
         let last_request = request_count + 1 >= CLOUDFLARE_HTTP2_REQUEST_LIMIT;
 
         tokio::select! {
@@ -197,6 +338,12 @@ pub async fn sender(
 
                         tokio::spawn(async move {
                             tokio::time::sleep(retry_after).await;
+
+                            if request.context.is_cancelled() {
+                                request.finish(Outcome::Cancelled);
+                                return;
+                            }
+
                             retry_tx.send(request).await.unwrap();
                         });
 
@@ -204,10 +351,17 @@ pub async fn sender(
                     },
                     Status::Known404 => {
                         tracing::warn!("{name} {identity} Known 404 target detected. Cacnceled.");
+                        request.finish(Outcome::Known404);
+                        continue;
+                    },
+                    Status::Cancelled => {
+                        tracing::info!("{name} {identity} Job cancelled. Skipped.");
+                        request.finish(Outcome::Cancelled);
                         continue;
                     },
                     Status::RetryLimitReached => {
                         tracing::warn!("{name} {identity} Retry limit reached. Canceled.");
+                        request.finish(Outcome::RetryLimitReached);
                         continue;
                     },
                     Status::Pass => (),
@@ -234,13 +388,34 @@ pub async fn sender(
                 let mut h2_header = Request::builder().method(Method::POST).uri(target_uri.as_str()).body(()).unwrap();
 
                 *h2_header.headers_mut() = headers.clone();
+                h2_header.headers_mut().insert(CONTENT_TYPE, request.context.content_type.clone());
 
-                let h2_body = request.context.body.clone();
+                let h2_body = request.body();
 
                 request_count += 1;
+                slot.sent();
+
+                let sent_at = Instant::now();
+
+                // GOAWAY may have come in while this one was being received.
+                if concurrency.is_closed() {
+                    retry_tx.send(request).await.unwrap();
+                    tracing::info!("{name} Connection is going away. Connection will be closed.");
+                    return Ok(());
+                }
 
                 let (response, mut respond) = match client.send_request(h2_header, false) {
                     Ok(v) => v,
+                    Err(e) if e.is_go_away() => {
+                        if concurrency.close() {
+                            slot.went_away(&concurrency, close_reason(&e));
+                        }
+
+                        // Never sent, so this isn't a retry.
+                        retry_tx.send(request).await.unwrap();
+                        tracing::info!("{name} Connection is going away. Connection will be closed.");
+                        return Ok(());
+                    },
                     Err(e) => {
                         let identity = identity.to_string();
                         retry_tx.send(request.into_retry()).await.unwrap();
@@ -257,21 +432,81 @@ pub async fn sender(
                 };
 
                 let retry_tx = retry_tx.clone();
+                let response_slot = slot.clone();
 
                 tokio::spawn(async move {
-                    response_handling(name, request, response, permit, retry_tx, limiter).await
+                    response_handling(name, request, response, permit, retry_tx, limiter, response_slot, sent_at).await
                 });
 
+                if replacement.is_none() && request_count + CONNECTION_ROTATION_MARGIN >= CLOUDFLARE_HTTP2_REQUEST_LIMIT {
+                    tracing::info!("{name} Nearing cloudflare HTTP/2 limit. Preparing a replacement connection.");
+                    let replacement_slot = slot.clone();
+
+                    replacement = Some(tokio::spawn(async move {
+                        let (client, connection) = setup_connection(from, to).await?;
+                        let (ping_pong, concurrency) = drive(name, &replacement_slot, connection);
+                        AHResult::Ok((client, ping_pong, concurrency))
+                    }));
+                }
+
                 if last_request {
-                    tracing::info!("{name} Reached to cloudflare HTTP/2 limit. Connection will be closed.");
-                    return Ok(());
+                    slot.draining();
+
+                    let prepared = match replacement.take() {
+                        Some(replacement) => replacement.await.map_err(anyhow::Error::from).and_then(|result| result),
+                        None => Err(anyhow::anyhow!("No replacement connection")),
+                    };
+
+                    let prepared = match prepared {
+                        Ok((new_client, mut new_ping_pong, new_concurrency)) => {
+                            alive(&mut new_ping_pong, &new_concurrency)
+                                .await
+                                .map(|()| (new_client, new_ping_pong, new_concurrency))
+                        },
+                        Err(e) => Err(e),
+                    };
+
+                    let (new_client, new_ping_pong, new_concurrency) = match prepared {
+                        Ok(v) => v,
+                        Err(e) => {
+                            tracing::info!("{name} Reached to cloudflare HTTP/2 limit. Connection will be closed. ({e:#})");
+                            return Ok(());
+                        },
+                    };
+
+                    tracing::info!("{name} Reached to cloudflare HTTP/2 limit. Handing over to the replacement connection.");
+
+                    crate::events::publish(Event::Connection {
+                        name,
+                        state: ConnectionState::Closed,
+                    });
+
+                    // Dropping the old client lets its connection finish the streams in flight and close.
+                    client = new_client;
+                    (ping_pong, concurrency) = (new_ping_pong, new_concurrency);
+                    activate(name, slot, &concurrency);
+                    request_count = 0;
+                    ping_interval.reset();
                 }
             },
-            _ = tokio::time::sleep(Duration::from_secs(30)) => {
+            _ = ping_interval.tick() => {
                 tracing::debug!("{name} ping");
                 let ping = h2::Ping::opaque();
 
+                let sent_at = Instant::now();
+
                 ping_pong.ping(ping).await.context("Failed to send ping")?;
+
+                let rtt = sent_at.elapsed();
+
+                if slot.pinged(rtt) {
+                    slot.draining();
+                    anyhow::bail!("Edge degraded (ping RTT {}ms), reconnecting", rtt.as_millis());
+                }
+
+                if slot.proven() {
+                    slot.breaker().succeeded();
+                }
             }
         }
     }
@@ -279,25 +514,70 @@ pub async fn sender(
 
 pub async fn sender_loop(
     name: &'static str,
+    pool: Pool,
     from: SocketAddrV4,
     to: SocketAddrV4,
     request_rx: JobReceiver,
     retry_tx: JobSender,
     limiter: &'static Limiter,
 ) -> ! {
+    let slot = crate::registry::register(name, pool, from, to);
+
     loop {
-        match sender(
+        let wait = slot.breaker().wait();
+
+        if !wait.is_zero() {
+            slot.backing_off();
+            tokio::time::sleep(wait).await;
+            continue;
+        }
+
+        slot.connecting();
+
+        let result = sender(
             name,
             from,
             to,
             request_rx.clone(),
             retry_tx.clone(),
             limiter,
+            &slot,
         )
         .await
-        {
-            Ok(()) => tracing::info!("{name} Sender is closed normally, restarting..."),
-            Err(e) => tracing::info!("{name} Sender is closed unexpectedly {e:?}, restarting..."),
+        .and_then(|()| {
+            // Closing right after opening, without a single response, is as bad as not connecting.
+            anyhow::ensure!(
+                slot.proven(),
+                "Connection closed before serving any request"
+            );
+            Ok(())
+        });
+
+        match result {
+            Ok(()) => {
+                slot.breaker().succeeded();
+
+                tracing::info!("{name} Sender is closed normally, restarting...");
+                crate::events::publish(Event::Connection {
+                    name,
+                    state: ConnectionState::Closed,
+                });
+            }
+            Err(e) => {
+                slot.failed();
+
+                if slot.breaker().failed() {
+                    tracing::warn!(
+                        "{name} Marked unhealthy after repeated failures, pausing reconnects"
+                    );
+                }
+
+                tracing::info!("{name} Sender is closed unexpectedly {e:?}, restarting...");
+                crate::events::publish(Event::Connection {
+                    name,
+                    state: ConnectionState::Failed,
+                });
+            }
         }
     }
 }
@@ -4,6 +4,7 @@ use anyhow::{Context, Result as AHResult};
 use hickory_resolver::Resolver;
 
 use crate::limiter::Limiter;
+use crate::registry::Pool;
 use crate::request::JobSender;
 
 async fn query_discord_ips() -> AHResult<Vec<Ipv4Addr>> {
@@ -30,7 +31,7 @@ pub async fn initialize(
     sender_ips: &[Ipv4Addr],
     multiplier: u8,
     rty_multiplier: u8,
-) -> AHResult<(JobSender, &'static Limiter)> {
+) -> AHResult<(JobSender, JobSender, &'static Limiter)> {
     let target_ips = query_discord_ips().await?;
 
     let target_socks: Vec<_> = target_ips
@@ -63,7 +64,7 @@ pub async fn initialize(
 
                 tokio::spawn(async move {
                     let name = &*format!("C{sock_no} {from}-{to}").leak();
-                    crate::conn::sender_loop(name, from, to, rx, tx, limiter).await;
+                    crate::conn::sender_loop(name, Pool::Primary, from, to, rx, tx, limiter).await;
                 });
             }
         }
@@ -79,11 +80,11 @@ pub async fn initialize(
 
                 tokio::spawn(async move {
                     let name = &*format!("R{sock_no} {from}-{to}").leak();
-                    crate::conn::sender_loop(name, from, to, rx, tx, limiter).await;
+                    crate::conn::sender_loop(name, Pool::Retry, from, to, rx, tx, limiter).await;
                 });
             }
         }
     }
 
-    Ok((tx, limiter))
+    Ok((tx, retry_tx, limiter))
 }
@@ -0,0 +1,79 @@
+use std::hash::{BuildHasher, RandomState};
+use std::time::{Duration, Instant};
+
+use papaya::{Compute, HashMap, Operation};
+
+use crate::discord::WebhookId;
+use crate::multipart::Attachment;
+
+/// Where a message actually lands: the webhook plus its query (e.g. `thread_id`), minus `wait`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Destination {
+    webhook: WebhookId,
+    query: Vec<(String, String)>,
+    thread_name: Option<String>,
+}
+
+impl Destination {
+    pub fn new(webhook: &WebhookId, target: &url::Url, thread_name: Option<&str>) -> Self {
+        let mut query: Vec<_> = target
+            .query_pairs()
+            .filter(|(k, _)| k != "wait")
+            .map(|(k, v)| (k.into_owned(), v.into_owned()))
+            .collect();
+
+        query.sort();
+
+        Self {
+            webhook: webhook.clone(),
+            query,
+            thread_name: thread_name.map(str::to_owned),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Deduplicator {
+    window: Duration,
+    hasher: RandomState,
+    seen: HashMap<(u64, Destination), Instant>,
+}
+
+impl Deduplicator {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            hasher: RandomState::new(),
+            seen: HashMap::default(),
+        }
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Hashes the message before multipart encoding, whose random boundary would differ per job.
+    pub fn hash_body(&self, payload_json: &[u8], attachments: &[Attachment]) -> u64 {
+        self.hasher.hash_one((payload_json, attachments))
+    }
+
+    /// Records the pair and returns `true` unless it was already admitted within the window.
+    pub fn admit(&self, body_hash: u64, destination: Destination) -> bool {
+        let now = Instant::now();
+        let expires_at = now + self.window;
+
+        let seen = self.seen.pin();
+
+        let result = seen.compute((body_hash, destination), |entry| match entry {
+            Some((_, expires)) if *expires > now => Operation::Abort(()),
+            _ => Operation::Insert(expires_at),
+        });
+
+        !matches!(result, Compute::Aborted(()))
+    }
+
+    pub fn purge(&self) {
+        let now = Instant::now();
+        self.seen.pin().retain(|_, expires| *expires > now);
+    }
+}
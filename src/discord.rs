@@ -1,6 +1,251 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+const WEBHOOK_HOSTS: &[&str] = &[
+    "discord.com",
+    "discordapp.com",
+    "canary.discord.com",
+    "ptb.discord.com",
+];
+
+const CONTENT_LIMIT: usize = 2000;
+const USERNAME_LIMIT: usize = 80;
+const THREAD_NAME_LIMIT: usize = 100;
+const EMBEDS_LIMIT: usize = 10;
+const EMBEDS_TOTAL_LIMIT: usize = 6000;
+const EMBED_TITLE_LIMIT: usize = 256;
+const EMBED_DESCRIPTION_LIMIT: usize = 4096;
+const EMBED_FIELDS_LIMIT: usize = 25;
+const EMBED_FIELD_NAME_LIMIT: usize = 256;
+const EMBED_FIELD_VALUE_LIMIT: usize = 1024;
+const EMBED_FOOTER_TEXT_LIMIT: usize = 2048;
+const EMBED_AUTHOR_NAME_LIMIT: usize = 256;
 
 #[derive(Debug, Deserialize)]
 pub struct Ratelimit {
     pub retry_after: f32,
 }
+
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InvalidWebhook {
+    UnsupportedScheme,
+    UnknownHost,
+    MalformedPath,
+}
+
+fn is_version(segment: &str) -> bool {
+    segment
+        .strip_prefix('v')
+        .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+}
+
+fn is_token(segment: &str) -> bool {
+    !segment.is_empty()
+        && segment
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+/// Canonical identity of a webhook, independent of host, API version and query.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct WebhookId {
+    pub id: u64,
+    pub token: String,
+}
+
+impl WebhookId {
+    /// Accepts only `https://{discord host}/api[/vN]/webhooks/{id}/{token}`.
+    pub fn parse(url: &url::Url) -> Result<Self, InvalidWebhook> {
+        if url.scheme() != "https" {
+            return Err(InvalidWebhook::UnsupportedScheme);
+        }
+
+        match url.host_str() {
+            Some(host) if WEBHOOK_HOSTS.contains(&host) => {}
+            _ => return Err(InvalidWebhook::UnknownHost),
+        }
+
+        let mut segments: Vec<&str> = url
+            .path_segments()
+            .ok_or(InvalidWebhook::MalformedPath)?
+            .collect();
+
+        if segments.len() == 6 && is_version(segments[1]) {
+            segments.remove(1);
+        }
+
+        match segments.as_slice() {
+            ["api", "webhooks", id, token] if is_token(token) => Ok(Self {
+                id: id.parse().map_err(|_| InvalidWebhook::MalformedPath)?,
+                token: token.to_string(),
+            }),
+            _ => Err(InvalidWebhook::MalformedPath),
+        }
+    }
+
+    pub fn to_url(&self) -> url::Url {
+        let url = format!(
+            "https://discord.com/api/webhooks/{}/{}",
+            self.id, self.token
+        );
+        url::Url::parse(&url).unwrap()
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct InvalidField {
+    pub field: String,
+    pub message: String,
+}
+
+struct Validator {
+    errors: Vec<InvalidField>,
+}
+
+impl Validator {
+    fn reject(&mut self, field: impl Into<String>, message: impl Into<String>) {
+        self.errors.push(InvalidField {
+            field: field.into(),
+            message: message.into(),
+        });
+    }
+
+    /// Returns the length in characters, which is what Discord's limits count.
+    fn text(&mut self, value: &Value, field: &str, limit: usize) -> usize {
+        match value {
+            Value::Null => 0,
+            Value::String(s) => {
+                let len = s.chars().count();
+                if len > limit {
+                    self.reject(field, format!("must be at most {limit} characters"));
+                }
+                len
+            }
+            _ => {
+                self.reject(field, "must be a string");
+                0
+            }
+        }
+    }
+
+    fn array<'a>(&mut self, value: &'a Value, field: &str, limit: usize) -> &'a [Value] {
+        match value {
+            Value::Null => &[],
+            Value::Array(values) => {
+                if values.len() > limit {
+                    self.reject(field, format!("must have at most {limit} items"));
+                }
+                values
+            }
+            _ => {
+                self.reject(field, "must be an array");
+                &[]
+            }
+        }
+    }
+
+    fn embed(&mut self, embed: &Value, field: &str) -> usize {
+        if !embed.is_object() {
+            self.reject(field, "must be an object");
+            return 0;
+        }
+
+        let mut total = 0;
+
+        total += self.text(
+            &embed["title"],
+            &format!("{field}.title"),
+            EMBED_TITLE_LIMIT,
+        );
+        total += self.text(
+            &embed["description"],
+            &format!("{field}.description"),
+            EMBED_DESCRIPTION_LIMIT,
+        );
+        total += self.text(
+            &embed["footer"]["text"],
+            &format!("{field}.footer.text"),
+            EMBED_FOOTER_TEXT_LIMIT,
+        );
+        total += self.text(
+            &embed["author"]["name"],
+            &format!("{field}.author.name"),
+            EMBED_AUTHOR_NAME_LIMIT,
+        );
+
+        let fields = self.array(
+            &embed["fields"],
+            &format!("{field}.fields"),
+            EMBED_FIELDS_LIMIT,
+        );
+
+        for (i, embed_field) in fields.iter().enumerate() {
+            let path = format!("{field}.fields[{i}]");
+            total += self.text(
+                &embed_field["name"],
+                &format!("{path}.name"),
+                EMBED_FIELD_NAME_LIMIT,
+            );
+            total += self.text(
+                &embed_field["value"],
+                &format!("{path}.value"),
+                EMBED_FIELD_VALUE_LIMIT,
+            );
+        }
+
+        match &embed["color"] {
+            Value::Null => {}
+            Value::Number(n) if n.as_u64().is_some_and(|color| color <= 0xFFFFFF) => {}
+            _ => self.reject(
+                format!("{field}.color"),
+                "must be an integer between 0 and 16777215",
+            ),
+        }
+
+        total
+    }
+}
+
+/// Checks an execute-webhook payload against Discord's documented limits.
+pub fn validate_message(body: &Value, has_attachments: bool) -> Result<(), Vec<InvalidField>> {
+    let mut v = Validator { errors: vec![] };
+
+    if !body.is_object() {
+        v.reject("", "must be an object");
+        return Err(v.errors);
+    }
+
+    let content_len = v.text(&body["content"], "content", CONTENT_LIMIT);
+    v.text(&body["username"], "username", USERNAME_LIMIT);
+    v.text(&body["thread_name"], "thread_name", THREAD_NAME_LIMIT);
+
+    let embeds = v.array(&body["embeds"], "embeds", EMBEDS_LIMIT);
+
+    let embeds_total: usize = embeds
+        .iter()
+        .enumerate()
+        .map(|(i, embed)| v.embed(embed, &format!("embeds[{i}]")))
+        .sum();
+
+    if embeds_total > EMBEDS_TOTAL_LIMIT {
+        v.reject(
+            "embeds",
+            format!("must have at most {EMBEDS_TOTAL_LIMIT} characters in total"),
+        );
+    }
+
+    let has_components = body["components"]
+        .as_array()
+        .is_some_and(|components| !components.is_empty());
+
+    if content_len == 0 && embeds.is_empty() && !has_components && !has_attachments {
+        v.reject("", "must have content, embeds, components or attachments");
+    }
+
+    if v.errors.is_empty() {
+        Ok(())
+    } else {
+        Err(v.errors)
+    }
+}
@@ -0,0 +1,73 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::request::Outcome;
+
+const EVENTS_CAPACITY: usize = 4096;
+
+static EVENTS: Lazy<broadcast::Sender<Arc<Event>>> =
+    Lazy::new(|| broadcast::channel(EVENTS_CAPACITY).0);
+
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionState {
+    Open,
+    Closed,
+    Failed,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    Target {
+        job: String,
+        identity: String,
+        webhook_id: u64,
+        outcome: Outcome,
+    },
+    Ratelimited {
+        job: String,
+        identity: String,
+        webhook_id: u64,
+        retry_after: f32,
+    },
+    Finished {
+        job: String,
+        identity: String,
+        issuer: String,
+        outcomes: BTreeMap<Outcome, usize>,
+    },
+    Connection {
+        name: &'static str,
+        state: ConnectionState,
+    },
+}
+
+impl Event {
+    pub fn job(&self) -> Option<&str> {
+        match self {
+            Self::Target { job, .. }
+            | Self::Ratelimited { job, .. }
+            | Self::Finished { job, .. } => Some(job),
+            Self::Connection { .. } => None,
+        }
+    }
+}
+
+/// Whether anyone is listening, so callers can skip building events nobody reads.
+pub fn observed() -> bool {
+    EVENTS.receiver_count() != 0
+}
+
+pub fn publish(event: Event) {
+    // No subscribers is not an error.
+    let _ = EVENTS.send(Arc::new(event));
+}
+
+pub fn subscribe() -> broadcast::Receiver<Arc<Event>> {
+    EVENTS.subscribe()
+}
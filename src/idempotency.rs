@@ -0,0 +1,50 @@
+use std::time::{Duration, Instant};
+
+use papaya::{Compute, HashMap, Operation};
+
+#[derive(Debug)]
+pub struct IdempotencyKeys {
+    ttl: Duration,
+    /// By (token name, key), so callers can't see each other's jobs through a shared key.
+    jobs: HashMap<(String, String), (String, Instant)>,
+}
+
+impl IdempotencyKeys {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            jobs: HashMap::default(),
+        }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Binds `issuer`'s `key` to `job_id`, or returns the job already bound to it within the TTL.
+    pub fn claim(&self, issuer: &str, key: &str, job_id: &str) -> Result<(), String> {
+        let now = Instant::now();
+        let expires_at = now + self.ttl;
+
+        let jobs = self.jobs.pin();
+
+        let result = jobs.compute((issuer.to_owned(), key.to_owned()), |entry| match entry {
+            Some((_, (job_id, expires))) if *expires > now => Operation::Abort(job_id.clone()),
+            _ => Operation::Insert((job_id.to_owned(), expires_at)),
+        });
+
+        match result {
+            Compute::Aborted(job_id) => Err(job_id),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn release(&self, issuer: &str, key: &str) {
+        self.jobs.pin().remove(&(issuer.to_owned(), key.to_owned()));
+    }
+
+    pub fn purge(&self) {
+        let now = Instant::now();
+        self.jobs.pin().retain(|_, (_, expires)| *expires > now);
+    }
+}
@@ -0,0 +1,75 @@
+use std::sync::{Arc, Weak};
+
+use papaya::HashMap;
+use serde::Serialize;
+
+use crate::request::{Context, Outcome};
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Cancelled {
+    pub job_id: String,
+    pub delivered: usize,
+    pub pending: usize,
+}
+
+#[derive(Debug)]
+struct Job {
+    issuer: String,
+    contexts: Vec<Weak<Context>>,
+}
+
+/// Contexts of jobs that still have targets in flight, by job ID.
+#[derive(Debug, Default)]
+pub struct Jobs {
+    jobs: HashMap<String, Job>,
+}
+
+impl Jobs {
+    pub fn register(&self, job_id: &str, issuer: &str, contexts: &[Arc<Context>]) {
+        let job = Job {
+            issuer: issuer.to_owned(),
+            contexts: contexts.iter().map(Arc::downgrade).collect(),
+        };
+
+        self.jobs.pin().insert(job_id.to_owned(), job);
+    }
+
+    /// Returns `None` once every target of the job is settled, or if it never existed
+    /// or belongs to someone other than `issuer` (`None` matches any issuer).
+    pub fn cancel(&self, job_id: &str, issuer: Option<&str>) -> Option<Cancelled> {
+        let jobs = self.jobs.pin();
+        let job = jobs
+            .get(job_id)
+            .filter(|job| issuer.is_none_or(|issuer| job.issuer == issuer))?;
+        let contexts: Vec<_> = job.contexts.iter().filter_map(Weak::upgrade).collect();
+
+        if contexts.is_empty() {
+            jobs.remove(job_id);
+            return None;
+        }
+
+        let mut delivered = 0;
+        let mut pending = 0;
+
+        for context in &contexts {
+            context.cancel();
+            delivered += context.outcomes.count(Outcome::Delivered);
+            // Minus the reference held right here.
+            pending += Arc::strong_count(context) - 1;
+        }
+
+        Some(Cancelled {
+            job_id: job_id.to_owned(),
+            delivered,
+            pending,
+        })
+    }
+
+    pub fn purge(&self) {
+        self.jobs.pin().retain(|_, job| {
+            job.contexts
+                .iter()
+                .any(|context| context.strong_count() != 0)
+        });
+    }
+}
@@ -2,6 +2,7 @@ use std::time::{Duration, Instant};
 
 use papaya::{HashMap, HashSet};
 
+use crate::discord::WebhookId;
 use crate::request::Request;
 
 #[derive(Debug)]
@@ -10,42 +11,51 @@ pub enum Status {
     Ratelimited(Duration),
     Known404,
     RetryLimitReached,
+    Cancelled,
 }
 
 #[derive(Debug, Default)]
 pub struct Limiter {
-    notfound_set: HashSet<url::Url>,
-    ratelimits: HashMap<url::Url, Instant>,
+    notfound_set: HashSet<WebhookId>,
+    ratelimits: HashMap<WebhookId, Instant>,
 }
 
 impl Limiter {
     pub fn notfounds(&self) -> Vec<url::Url> {
-        self.notfound_set.pin().iter().cloned().collect()
+        self.notfound_set
+            .pin()
+            .iter()
+            .map(WebhookId::to_url)
+            .collect()
     }
 
     pub fn current(&self, request: &Request) -> Status {
+        if request.context.is_cancelled() {
+            return Status::Cancelled;
+        }
+
         if request.retry_count > request.context.retry_limit {
             return Status::RetryLimitReached;
         }
 
-        if self.notfound_set.pin().contains(&request.target) {
+        if self.notfound_set.pin().contains(&request.webhook) {
             return Status::Known404;
         }
 
-        if let Some(ratelimit_to) = self.ratelimits.pin().get(&request.target) {
-            if let Some(duration) = ratelimit_to.checked_duration_since(Instant::now()) {
-                return Status::Ratelimited(duration);
-            }
+        if let Some(ratelimit_to) = self.ratelimits.pin().get(&request.webhook)
+            && let Some(duration) = ratelimit_to.checked_duration_since(Instant::now())
+        {
+            return Status::Ratelimited(duration);
         }
 
         Status::Pass
     }
 
-    pub fn tell_notfound(&self, target: &url::Url) {
+    pub fn tell_notfound(&self, target: &WebhookId) {
         self.notfound_set.pin().insert(target.to_owned());
     }
 
-    pub fn clear_notfounds<S: std::borrow::Borrow<url::Url>>(&self, targets: &[S]) {
+    pub fn clear_notfounds<S: std::borrow::Borrow<WebhookId>>(&self, targets: &[S]) {
         let set = self.notfound_set.pin();
 
         for target in targets {
@@ -53,7 +63,7 @@ impl Limiter {
         }
     }
 
-    pub fn tell_ratelimit(&self, target: &url::Url, retry_after: f32) -> Duration {
+    pub fn tell_ratelimit(&self, target: &WebhookId, retry_after: f32) -> Duration {
         let delta_time = Duration::from_secs_f32(retry_after);
         let limit_to = Instant::now() + delta_time;
 
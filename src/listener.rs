@@ -0,0 +1,301 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::Path;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context as _, Result as AHResult};
+use axum::extract::connect_info::Connected;
+use axum::serve::{IncomingStream, Listener};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tokio::sync::mpsc;
+use tokio_rustls::{
+    TlsAcceptor,
+    rustls::{
+        RootCertStore, ServerConfig,
+        pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+        server::WebPkiClientVerifier,
+    },
+};
+
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const TLS_HANDSHAKE_BACKLOG: usize = 128;
+
+pub trait Io: AsyncRead + AsyncWrite + Send + Unpin + 'static {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> Io for T {}
+
+/// Builds the server config from PEM files. With `client_ca`, clients must present
+/// a certificate issued by it.
+pub fn tls_config(
+    cert: &Path,
+    key: &Path,
+    client_ca: Option<&Path>,
+) -> AHResult<Arc<ServerConfig>> {
+    let certs = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("Failed to read certificates from {}", cert.display()))?;
+
+    let key = PrivateKeyDer::from_pem_file(key)
+        .with_context(|| format!("Failed to read private key from {}", key.display()))?;
+
+    let builder = ServerConfig::builder();
+
+    let builder = match client_ca {
+        Some(client_ca) => {
+            let mut roots = RootCertStore::empty();
+
+            for ca in CertificateDer::pem_file_iter(client_ca)
+                .with_context(|| format!("Failed to read {}", client_ca.display()))?
+            {
+                roots
+                    .add(ca.with_context(|| format!("Failed to read {}", client_ca.display()))?)
+                    .context("Invalid client CA certificate")?;
+            }
+
+            let verifier = WebPkiClientVerifier::builder(Arc::new(roots))
+                .build()
+                .context("Failed to build client certificate verifier")?;
+
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let mut config = builder
+        .with_single_cert(certs, key)
+        .context("Invalid certificate or private key")?;
+
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+    Ok(Arc::new(config))
+}
+
+/// Where the control API listens: `host:port`, or `unix:/path.sock` on unix.
+#[derive(Clone, Debug)]
+pub enum ListenAddr {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl FromStr for ListenAddr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            #[cfg(unix)]
+            return Ok(Self::Unix(PathBuf::from(path)));
+
+            #[cfg(not(unix))]
+            return Err(format!(
+                "{path}: unix sockets are not supported on this platform"
+            ));
+        }
+
+        s.parse().map(Self::Tcp).map_err(|e| format!("{s}: {e}"))
+    }
+}
+
+/// Address of the client on the other end of an API connection.
+#[derive(Clone, Copy, Debug)]
+pub enum Peer {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix,
+}
+
+impl fmt::Display for Peer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(addr) => addr.fmt(f),
+            #[cfg(unix)]
+            Self::Unix => f.write_str("unix"),
+        }
+    }
+}
+
+impl Connected<IncomingStream<'_, ApiListener>> for Peer {
+    fn connect_info(stream: IncomingStream<'_, ApiListener>) -> Self {
+        *stream.remote_addr()
+    }
+}
+
+/// The control API listener: plain TCP, a unix socket, or TLS-terminating over either.
+pub enum ApiListener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+    Tls {
+        local_addr: Peer,
+        handshaken: mpsc::Receiver<(Box<dyn Io>, Peer)>,
+    },
+}
+
+impl ApiListener {
+    /// `socket_mode` sets the permission bits of a unix socket file.
+    pub async fn bind(
+        addr: ListenAddr,
+        socket_mode: Option<u32>,
+        tls: Option<Arc<ServerConfig>>,
+    ) -> AHResult<Self> {
+        let listener = match addr {
+            ListenAddr::Tcp(addr) => Self::Tcp(
+                TcpListener::bind(addr)
+                    .await
+                    .context("Failed to bind address")?,
+            ),
+            #[cfg(unix)]
+            ListenAddr::Unix(path) => Self::Unix(bind_unix(&path, socket_mode)?),
+        };
+
+        #[cfg(not(unix))]
+        let _ = socket_mode;
+
+        let Some(tls) = tls else {
+            return Ok(listener);
+        };
+
+        let local_addr = listener.local_addr()?;
+        let acceptor = TlsAcceptor::from(tls);
+        let (tx, handshaken) = mpsc::channel(TLS_HANDSHAKE_BACKLOG);
+
+        // Handshakes run off the accept loop so one slow client can't stall the others.
+        tokio::spawn(async move {
+            let mut listener = listener;
+
+            loop {
+                let (stream, peer) = listener.accept().await;
+                let acceptor = acceptor.clone();
+                let tx = tx.clone();
+
+                tokio::spawn(async move {
+                    let stream =
+                        match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream))
+                            .await
+                        {
+                            Ok(Ok(stream)) => stream,
+                            Ok(Err(e)) => {
+                                tracing::warn!("{peer} TLS handshake failed: {e}");
+                                return;
+                            }
+                            Err(_) => {
+                                tracing::warn!("{peer} TLS handshake timed out");
+                                return;
+                            }
+                        };
+
+                    let _ = tx.send((Box::new(stream) as Box<dyn Io>, peer)).await;
+                });
+            }
+        });
+
+        Ok(Self::Tls {
+            local_addr,
+            handshaken,
+        })
+    }
+}
+
+/// Binds `path`, replacing a socket left behind by a previous run.
+/// The socket is created in a private directory and only renamed into place once
+/// `mode` is set, so it is never reachable with the umask's permissions.
+#[cfg(unix)]
+fn bind_unix(path: &Path, mode: Option<u32>) -> AHResult<UnixListener> {
+    use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        // The rename below would replace whatever is there.
+        anyhow::ensure!(
+            metadata.file_type().is_socket(),
+            "{} exists and is not a socket",
+            path.display()
+        );
+
+        match std::os::unix::net::UnixStream::connect(path) {
+            Ok(_) => anyhow::bail!("{} is in use by a running instance", path.display()),
+            Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => {
+                std::fs::remove_file(path)
+                    .with_context(|| format!("Failed to remove stale socket {}", path.display()))?;
+            }
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to probe {}", path.display()));
+            }
+        }
+    }
+
+    let file_name = path
+        .file_name()
+        .with_context(|| format!("{} has no file name", path.display()))?;
+
+    let mut staging_name = std::ffi::OsString::from(".");
+    staging_name.push(file_name);
+    staging_name.push(".bind");
+    let staging = path.with_file_name(staging_name);
+
+    // Left over if a previous run died while binding.
+    let _ = std::fs::remove_dir_all(&staging);
+
+    std::fs::DirBuilder::new()
+        .mode(0o700)
+        .create(&staging)
+        .with_context(|| format!("Failed to create {}", staging.display()))?;
+
+    let staged = staging.join(file_name);
+
+    let result = (|| {
+        let listener = UnixListener::bind(&staged)
+            .with_context(|| format!("Failed to bind {}", staged.display()))?;
+
+        if let Some(mode) = mode {
+            std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(mode))
+                .with_context(|| format!("Failed to set permissions of {}", staged.display()))?;
+        }
+
+        std::fs::rename(&staged, path)
+            .with_context(|| format!("Failed to move socket to {}", path.display()))?;
+
+        Ok(listener)
+    })();
+
+    let _ = std::fs::remove_dir_all(&staging);
+
+    result
+}
+
+impl Listener for ApiListener {
+    type Io = Box<dyn Io>;
+    type Addr = Peer;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self {
+            Self::Tcp(listener) => {
+                let (stream, addr) = Listener::accept(listener).await;
+                (Box::new(stream), Peer::Tcp(addr))
+            }
+            #[cfg(unix)]
+            Self::Unix(listener) => {
+                let (stream, _) = Listener::accept(listener).await;
+                (Box::new(stream), Peer::Unix)
+            }
+            Self::Tls { handshaken, .. } => {
+                handshaken.recv().await.expect("TLS accept loop is gone")
+            }
+        }
+    }
+
+    fn local_addr(&self) -> std::io::Result<Self::Addr> {
+        match self {
+            Self::Tcp(listener) => listener.local_addr().map(Peer::Tcp),
+            #[cfg(unix)]
+            Self::Unix(_) => Ok(Peer::Unix),
+            Self::Tls { local_addr, .. } => Ok(*local_addr),
+        }
+    }
+}
@@ -1,14 +1,29 @@
-use std::net::{Ipv4Addr, SocketAddr};
+use std::net::Ipv4Addr;
+use std::path::PathBuf;
+use std::time::Duration;
 
 use clap::Parser;
 
+mod auth;
+mod breaker;
+mod callback;
+mod concurrency;
 mod conn;
 mod conn_initializer;
+mod dedup;
 mod discord;
+mod events;
+mod idempotency;
+mod jobs;
 mod limiter;
+mod listener;
+mod multipart;
+mod namesgenerator;
+mod registry;
 mod request;
+mod schedule;
+mod template;
 mod web;
-mod namesgenerator;
 
 #[derive(Debug, Parser)]
 struct Cli {
@@ -24,16 +39,65 @@ struct Cli {
     #[clap(long, env, default_value_t = 1)]
     rty_multiplier: u8,
 
+    /// Token with every scope, registered as "default".
+    #[clap(long, env)]
+    auth_token: Option<String>,
+
+    /// JSON array of { name, token, scopes, targets_per_minute }.
     #[clap(long, env)]
-    auth_token: String,
+    tokens_file: Option<PathBuf>,
 
+    /// `host:port`, or `unix:/path.sock` for a unix domain socket.
     #[clap(long, env, default_value = "0.0.0.0:3000")]
-    listen: SocketAddr,
+    listen: listener::ListenAddr,
+
+    /// Octal permission bits for the unix socket file, e.g. 660.
+    #[clap(long, env, value_parser = parse_mode)]
+    socket_mode: Option<u32>,
+
+    /// PEM certificate chain. Serves the API over TLS together with --tls-key.
+    #[clap(long, env, requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+
+    #[clap(long, env, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// PEM CA bundle. Clients must present a certificate issued by it.
+    #[clap(long, env, requires = "tls_cert")]
+    tls_client_ca: Option<PathBuf>,
+
+    /// Suppress an identical (body, target) pair queued again within this many seconds.
+    #[clap(long, env)]
+    dedup_window: Option<u64>,
+
+    /// How long (in seconds) an Idempotency-Key keeps resolving to its original job.
+    #[clap(long, env, default_value_t = 3600)]
+    idempotency_ttl: u64,
+
+    /// How far (in seconds) an X-Timestamp on a signed request may be from the current time.
+    #[clap(long, env, default_value_t = 300)]
+    signature_window: u64,
+
+    /// Connections to Discord required before /readyz reports ready.
+    #[clap(long, env, default_value_t = 1)]
+    ready_min_connections: usize,
+
+    /// Queued targets at or above which /readyz reports not ready.
+    #[clap(long, env, default_value_t = 100_000)]
+    ready_max_queue_depth: usize,
+
+    /// HMAC-SHA256 key for signing job callbacks. Callbacks are refused without it.
+    #[clap(long, env)]
+    callback_secret: Option<String>,
+}
+
+fn parse_mode(mode: &str) -> Result<u32, String> {
+    u32::from_str_radix(mode, 8).map_err(|e| format!("{mode}: {e}"))
 }
 
 #[tokio::main]
 async fn main() {
-    let cli = Cli::parse();
+    let mut cli = Cli::parse();
 
     let format = tracing_subscriber::fmt::format()
         .with_target(false)
@@ -44,7 +108,27 @@ async fn main() {
         .with_max_level(tracing::Level::INFO)
         .init();
 
-    let (sender, limiter) = conn_initializer::initialize(
+    let tokens = auth::Tokens::load(
+        cli.tokens_file.as_deref(),
+        cli.auth_token.take(),
+        Duration::from_secs(cli.signature_window),
+    )
+    .expect("failed to load API tokens");
+    let tokens = &*Box::leak(Box::new(tokens));
+
+    let tls = cli
+        .tls_cert
+        .as_deref()
+        .zip(cli.tls_key.as_deref())
+        .map(|(cert, key)| listener::tls_config(cert, key, cli.tls_client_ca.as_deref()))
+        .transpose()
+        .expect("failed to load TLS config");
+
+    let listener = listener::ApiListener::bind(cli.listen, cli.socket_mode, tls)
+        .await
+        .expect("failed to bind API listener");
+
+    let (sender, retry_sender, limiter) = conn_initializer::initialize(
         &cli.retry_ips,
         &cli.sender_ips,
         cli.multiplier,
@@ -53,5 +137,25 @@ async fn main() {
     .await
     .expect("failed to initialize connection");
 
-    web::run(cli.listen, sender, limiter, &cli.auth_token).await.unwrap();
+    let dedup_window = cli.dedup_window.map(Duration::from_secs);
+    let notifier = cli
+        .callback_secret
+        .as_deref()
+        .map(callback::Notifier::spawn);
+
+    let config = web::Config {
+        sender,
+        retry_sender,
+        limiter,
+        dedup_window,
+        idempotency_ttl: Duration::from_secs(cli.idempotency_ttl),
+        notifier,
+        tokens,
+        readiness: web::Readiness {
+            min_connections: cli.ready_min_connections,
+            max_queue_depth: cli.ready_max_queue_depth,
+        },
+    };
+
+    web::run(listener, config).await.unwrap();
 }
@@ -0,0 +1,81 @@
+use bytes::{BufMut, Bytes, BytesMut};
+use http::HeaderValue;
+use rand::{Rng, RngExt, distr::Alphanumeric};
+
+#[derive(Clone, Debug, Hash)]
+pub struct Attachment {
+    pub filename: String,
+    pub content_type: String,
+    pub data: Bytes,
+}
+
+/// `multipart/form-data` framing for an execute-webhook call with attachments.
+#[derive(Clone, Debug)]
+pub struct Multipart {
+    boundary: String,
+    attachments: Vec<Attachment>,
+}
+
+fn sanitize(value: &str) -> String {
+    value
+        .chars()
+        .filter(|c| !c.is_control())
+        .map(|c| if c == '"' { '\'' } else { c })
+        .collect()
+}
+
+impl Multipart {
+    pub fn new<R: Rng>(rng: &mut R, attachments: Vec<Attachment>) -> Self {
+        let boundary = rng
+            .sample_iter(Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect();
+
+        Self {
+            boundary,
+            attachments,
+        }
+    }
+
+    pub fn attachments(&self) -> &[Attachment] {
+        &self.attachments
+    }
+
+    pub fn content_type(&self) -> HeaderValue {
+        format!("multipart/form-data; boundary={}", self.boundary)
+            .parse()
+            .unwrap()
+    }
+
+    pub fn encode(&self, payload_json: &[u8]) -> Bytes {
+        let boundary = &self.boundary;
+        let mut body = BytesMut::new();
+
+        body.put_slice(format!("--{boundary}\r\n").as_bytes());
+        body.put_slice(b"Content-Disposition: form-data; name=\"payload_json\"\r\n");
+        body.put_slice(b"Content-Type: application/json\r\n\r\n");
+        body.put_slice(payload_json);
+        body.put_slice(b"\r\n");
+
+        for (i, attachment) in self.attachments.iter().enumerate() {
+            let filename = sanitize(&attachment.filename);
+            let content_type = sanitize(&attachment.content_type);
+
+            body.put_slice(format!("--{boundary}\r\n").as_bytes());
+            body.put_slice(
+                format!(
+                    "Content-Disposition: form-data; name=\"files[{i}]\"; filename=\"{filename}\"\r\n"
+                )
+                .as_bytes(),
+            );
+            body.put_slice(format!("Content-Type: {content_type}\r\n\r\n").as_bytes());
+            body.put_slice(&attachment.data);
+            body.put_slice(b"\r\n");
+        }
+
+        body.put_slice(format!("--{boundary}--\r\n").as_bytes());
+
+        body.freeze()
+    }
+}
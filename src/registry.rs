@@ -0,0 +1,331 @@
+use std::net::SocketAddrV4;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use serde::Serialize;
+
+use crate::breaker::{Breaker, BreakerStats};
+use crate::concurrency::Concurrency;
+use crate::conn::CLOUDFLARE_HTTP2_REQUEST_LIMIT;
+
+/// Weight of a new sample in the smoothed RTT, as in TCP's SRTT.
+const RTT_SMOOTHING: f64 = 0.125;
+/// A ping is a spike when it takes this many times the smoothed RTT...
+const RTT_SPIKE_FACTOR: f64 = 4.0;
+/// ...and at least this long, so jitter on a fast edge doesn't count.
+const RTT_SPIKE_FLOOR: Duration = Duration::from_millis(100);
+/// Consecutive spikes after which the edge is considered degraded.
+const RTT_SPIKES_DEGRADED: usize = 2;
+
+/// Slots within this factor of the fastest one take work without delay.
+const DISPATCH_TOLERANCE: f64 = 1.2;
+/// Upper bound on how long a slow slot defers picking up work.
+const MAX_DISPATCH_HANDICAP: Duration = Duration::from_millis(50);
+
+/// How long an idle slot must stay connected before its pair counts as healthy again.
+const HEALTHY_UPTIME: Duration = Duration::from_secs(60);
+
+static SLOTS: Lazy<RwLock<Vec<Arc<Slot>>>> = Lazy::new(RwLock::default);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SlotState {
+    Backoff,
+    Connecting,
+    Open,
+    Draining,
+}
+
+/// Which queue a slot takes its work from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Pool {
+    Primary,
+    Retry,
+}
+
+#[derive(Debug)]
+struct Live {
+    state: SlotState,
+    established_at: Option<Instant>,
+    concurrency: Option<Arc<Concurrency>>,
+    last_ping_rtt: Option<Duration>,
+    smoothed_rtt: Option<Duration>,
+    rtt_spikes: usize,
+    last_close_reason: Option<String>,
+}
+
+/// One `sender_loop`, tracked across the connections it makes.
+#[derive(Debug)]
+pub struct Slot {
+    name: &'static str,
+    pool: Pool,
+    from: SocketAddrV4,
+    to: SocketAddrV4,
+    requests: AtomicUsize,
+    /// Smoothed time to response headers in microseconds, 0 until measured.
+    latency_us: AtomicU64,
+    errors: AtomicUsize,
+    goaways: AtomicUsize,
+    /// A response has come back since the slot last connected.
+    served: AtomicBool,
+    breaker: Arc<Breaker>,
+    live: Mutex<Live>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SlotStats {
+    pub name: &'static str,
+    pub pool: Pool,
+    pub from: SocketAddrV4,
+    pub to: SocketAddrV4,
+    pub state: SlotState,
+    pub requests_sent: usize,
+    pub request_limit: usize,
+    pub concurrency_limit: Option<usize>,
+    pub available_permits: Option<usize>,
+    pub last_ping_rtt_ms: Option<f64>,
+    pub smoothed_rtt_ms: Option<f64>,
+    pub smoothed_latency_ms: Option<f64>,
+    pub degraded: bool,
+    pub errors: usize,
+    pub goaways: usize,
+    pub last_close_reason: Option<String>,
+    pub uptime_secs: Option<f64>,
+    pub breaker: BreakerStats,
+}
+
+impl Slot {
+    pub fn connecting(&self) {
+        let mut live = self.live.lock().unwrap();
+        live.state = SlotState::Connecting;
+        live.established_at = None;
+        self.served.store(false, Ordering::Relaxed);
+        live.concurrency = None;
+        live.last_ping_rtt = None;
+        live.smoothed_rtt = None;
+        live.rtt_spikes = 0;
+    }
+
+    pub fn backing_off(&self) {
+        self.live.lock().unwrap().state = SlotState::Backoff;
+    }
+
+    /// Also called on each handover, which keeps the slot's uptime running.
+    pub fn opened(&self, concurrency: Arc<Concurrency>) {
+        self.requests.store(0, Ordering::Relaxed);
+        self.latency_us.store(0, Ordering::Relaxed);
+
+        let mut live = self.live.lock().unwrap();
+        live.state = SlotState::Open;
+        live.established_at.get_or_insert_with(Instant::now);
+        live.concurrency = Some(concurrency);
+    }
+
+    pub fn draining(&self) {
+        self.live.lock().unwrap().state = SlotState::Draining;
+    }
+
+    pub fn sent(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn failed(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The server sent GOAWAY on `concurrency`'s connection, which may already be a replaced one.
+    pub fn went_away(&self, concurrency: &Concurrency, reason: String) {
+        self.goaways.fetch_add(1, Ordering::Relaxed);
+
+        let mut live = self.live.lock().unwrap();
+
+        if live
+            .concurrency
+            .as_deref()
+            .is_some_and(|current| std::ptr::eq(current, concurrency))
+        {
+            live.state = SlotState::Draining;
+        }
+
+        live.last_close_reason = Some(reason);
+    }
+
+    pub fn closed(&self, reason: String) {
+        self.live.lock().unwrap().last_close_reason = Some(reason);
+    }
+
+    /// Records a ping RTT and returns whether the edge now looks degraded.
+    /// Spikes are kept out of the smoothed RTT so they can't raise the bar for the next one.
+    pub fn pinged(&self, rtt: Duration) -> bool {
+        let mut live = self.live.lock().unwrap();
+        live.last_ping_rtt = Some(rtt);
+
+        match live.smoothed_rtt {
+            Some(smoothed)
+                if rtt > RTT_SPIKE_FLOOR
+                    && rtt.as_secs_f64() > smoothed.as_secs_f64() * RTT_SPIKE_FACTOR =>
+            {
+                live.rtt_spikes += 1;
+            }
+            Some(smoothed) => {
+                live.smoothed_rtt =
+                    Some(smoothed.mul_f64(1.0 - RTT_SMOOTHING) + rtt.mul_f64(RTT_SMOOTHING));
+                live.rtt_spikes = 0;
+            }
+            None => live.smoothed_rtt = Some(rtt),
+        }
+
+        live.rtt_spikes >= RTT_SPIKES_DEGRADED
+    }
+
+    /// Folds a response latency into the smoothed one and returns the result.
+    /// Concurrent updates may drop a sample.
+    /// The first response since connecting proves the pair and resets its breaker.
+    pub fn responded(&self, latency: Duration) -> Duration {
+        if !self.served.swap(true, Ordering::Relaxed) {
+            self.breaker.succeeded();
+        }
+
+        let sample = latency.as_micros().max(1) as f64;
+
+        let smoothed = match self.latency_us.load(Ordering::Relaxed) {
+            0 => sample,
+            current => current as f64 * (1.0 - RTT_SMOOTHING) + sample * RTT_SMOOTHING,
+        };
+
+        self.latency_us.store(smoothed as u64, Ordering::Relaxed);
+
+        Duration::from_micros(smoothed as u64)
+    }
+
+    fn latency(&self) -> Option<Duration> {
+        match self.latency_us.load(Ordering::Relaxed) {
+            0 => None,
+            us => Some(Duration::from_micros(us)),
+        }
+    }
+
+    /// Response latency once known, the ping RTT before that.
+    fn score(&self) -> Option<Duration> {
+        self.latency()
+            .or_else(|| self.live.lock().unwrap().smoothed_rtt)
+    }
+
+    /// How long to wait before competing for the next job, so that idle faster slots
+    /// on the same queue get it first. Slow slots still take work whenever faster ones are busy.
+    pub fn dispatch_handicap(&self) -> Duration {
+        let Some(mine) = self.score() else {
+            return Duration::ZERO;
+        };
+
+        let best = SLOTS
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|slot| slot.pool == self.pool && slot.state() == SlotState::Open)
+            .filter_map(|slot| slot.score())
+            .min();
+
+        match best {
+            Some(best) if mine.as_secs_f64() > best.as_secs_f64() * DISPATCH_TOLERANCE => {
+                (mine - best).min(MAX_DISPATCH_HANDICAP)
+            }
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// Whether the slot has carried traffic, or stayed connected long enough without any.
+    /// A handshake alone doesn't prove the pair works; an edge may accept and drop at once.
+    pub fn proven(&self) -> bool {
+        self.served.load(Ordering::Relaxed)
+            || self
+                .live
+                .lock()
+                .unwrap()
+                .established_at
+                .is_some_and(|at| at.elapsed() >= HEALTHY_UPTIME)
+    }
+
+    pub fn breaker(&self) -> &Breaker {
+        &self.breaker
+    }
+
+    pub fn state(&self) -> SlotState {
+        self.live.lock().unwrap().state
+    }
+
+    fn stats(&self) -> SlotStats {
+        let live = self.live.lock().unwrap();
+
+        SlotStats {
+            name: self.name,
+            pool: self.pool,
+            from: self.from,
+            to: self.to,
+            state: live.state,
+            requests_sent: self.requests.load(Ordering::Relaxed),
+            request_limit: CLOUDFLARE_HTTP2_REQUEST_LIMIT,
+            concurrency_limit: live.concurrency.as_ref().map(|c| c.limit()),
+            available_permits: live.concurrency.as_ref().map(|c| c.available()),
+            last_ping_rtt_ms: live.last_ping_rtt.map(|rtt| rtt.as_secs_f64() * 1000.0),
+            smoothed_rtt_ms: live.smoothed_rtt.map(|rtt| rtt.as_secs_f64() * 1000.0),
+            smoothed_latency_ms: self.latency().map(|latency| latency.as_secs_f64() * 1000.0),
+            degraded: live.rtt_spikes >= RTT_SPIKES_DEGRADED,
+            errors: self.errors.load(Ordering::Relaxed),
+            goaways: self.goaways.load(Ordering::Relaxed),
+            last_close_reason: live.last_close_reason.clone(),
+            uptime_secs: live.established_at.map(|at| at.elapsed().as_secs_f64()),
+            breaker: self.breaker.stats(),
+        }
+    }
+}
+
+pub fn register(name: &'static str, pool: Pool, from: SocketAddrV4, to: SocketAddrV4) -> Arc<Slot> {
+    let slot = Arc::new(Slot {
+        name,
+        pool,
+        from,
+        to,
+        requests: AtomicUsize::new(0),
+        latency_us: AtomicU64::new(0),
+        errors: AtomicUsize::new(0),
+        goaways: AtomicUsize::new(0),
+        served: AtomicBool::new(false),
+        breaker: crate::breaker::get(from, to),
+        live: Mutex::new(Live {
+            state: SlotState::Connecting,
+            established_at: None,
+            concurrency: None,
+            last_ping_rtt: None,
+            smoothed_rtt: None,
+            rtt_spikes: 0,
+            last_close_reason: None,
+        }),
+    });
+
+    SLOTS.write().unwrap().push(slot.clone());
+    slot
+}
+
+/// Primary-pool slots currently holding an established HTTP/2 connection that takes new work.
+/// Retry slots never see new jobs, so they don't make the instance ready.
+pub fn open() -> usize {
+    SLOTS
+        .read()
+        .unwrap()
+        .iter()
+        .filter(|slot| slot.pool == Pool::Primary && slot.state() == SlotState::Open)
+        .count()
+}
+
+pub fn snapshot() -> Vec<SlotStats> {
+    SLOTS
+        .read()
+        .unwrap()
+        .iter()
+        .map(|slot| slot.stats())
+        .collect()
+}
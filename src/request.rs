@@ -1,14 +1,83 @@
+use std::collections::BTreeMap;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use serde::Serialize;
+
+use crate::callback::Callback;
+use crate::events::Event;
 
 pub type Job = crate::request::Request;
 pub type JobSender = async_channel::Sender<Job>;
 pub type JobReceiver = async_channel::Receiver<Job>;
 
-#[derive(Clone, Debug)]
+/// How a `Request` left the pipeline for good.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Delivered,
+    NotFound,
+    Known404,
+    Rejected,
+    RetryLimitReached,
+    Unexpected,
+    Cancelled,
+}
+
+impl Outcome {
+    const ALL: [Outcome; 7] = [
+        Outcome::Delivered,
+        Outcome::NotFound,
+        Outcome::Known404,
+        Outcome::Rejected,
+        Outcome::RetryLimitReached,
+        Outcome::Unexpected,
+        Outcome::Cancelled,
+    ];
+}
+
+#[derive(Debug, Default)]
+pub struct Outcomes([AtomicUsize; Outcome::ALL.len()]);
+
+impl Outcomes {
+    pub fn record(&self, outcome: Outcome) {
+        self.0[outcome as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn count(&self, outcome: Outcome) -> usize {
+        self.0[outcome as usize].load(Ordering::Relaxed)
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<Outcome, usize> {
+        Outcome::ALL
+            .iter()
+            .map(|outcome| (*outcome, self.count(*outcome)))
+            .collect()
+    }
+}
+
+#[derive(Debug)]
 pub struct Context {
     pub retry_limit: usize,
     pub body: bytes::Bytes,
+    pub content_type: http::HeaderValue,
+    pub job_id: String,
+    /// Name of the API token that submitted the job.
+    pub issuer: String,
     pub identity: String,
+    pub outcomes: Outcomes,
+    pub callback: Option<Callback>,
+    pub cancelled: AtomicBool,
+}
+
+impl Context {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -16,10 +85,47 @@ pub struct Request {
     pub context: Arc<Context>,
     pub retry_count: usize,
     pub target: url::Url,
+    pub webhook: crate::discord::WebhookId,
+    /// Replaces `context.body` for this target only.
+    pub body: Option<bytes::Bytes>,
     pub identity: String,
 }
 
 impl Request {
+    pub fn body(&self) -> bytes::Bytes {
+        match &self.body {
+            Some(body) => body.clone(),
+            None => self.context.body.clone(),
+        }
+    }
+
+    pub fn finish(&self, outcome: Outcome) {
+        self.context.outcomes.record(outcome);
+
+        let callback = self
+            .context
+            .callback
+            .as_ref()
+            .filter(|callback| callback.events);
+
+        if callback.is_none() && !crate::events::observed() {
+            return;
+        }
+
+        let event = Event::Target {
+            job: self.context.job_id.clone(),
+            identity: self.identity.clone(),
+            webhook_id: self.webhook.id,
+            outcome,
+        };
+
+        if let Some(callback) = callback {
+            callback.notifier.notify(&callback.url, &event);
+        }
+
+        crate::events::publish(event);
+    }
+
     pub fn into_retry(mut self) -> Self {
         self.retry_count += 1;
         self
@@ -29,6 +135,19 @@ impl Request {
 impl Drop for Context {
     fn drop(&mut self) {
         tracing::info!("{} Sent!", self.identity);
+
+        let event = Event::Finished {
+            job: self.job_id.clone(),
+            identity: self.identity.clone(),
+            issuer: self.issuer.clone(),
+            outcomes: self.outcomes.snapshot(),
+        };
+
+        if let Some(callback) = &self.callback {
+            callback.notifier.notify(&callback.url, &event);
+        }
+
+        crate::events::publish(event);
     }
 }
 
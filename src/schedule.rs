@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::request::{JobSender, Outcome, Request};
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ScheduledJob {
+    pub job_id: String,
+    pub issuer: String,
+    pub identity: String,
+    pub send_at: u64,
+    pub targets: usize,
+}
+
+#[derive(Debug)]
+struct Entry {
+    job: ScheduledJob,
+    requests: Vec<Request>,
+}
+
+/// The `SystemTime` of a UNIX `send_at`, or `None` when it can't be represented.
+pub fn send_at_time(send_at: u64) -> Option<SystemTime> {
+    UNIX_EPOCH.checked_add(Duration::from_secs(send_at))
+}
+
+/// Holds requests until their `send_at`, then hands them to the sender channel.
+#[derive(Debug)]
+pub struct Scheduler {
+    sender: JobSender,
+    /// Keyed by a counter; identities are made of generated names that can collide.
+    entries: Mutex<HashMap<u64, Entry>>,
+    next_key: AtomicU64,
+}
+
+impl Scheduler {
+    pub fn new(sender: JobSender) -> Self {
+        Self {
+            sender,
+            entries: Mutex::new(HashMap::new()),
+            next_key: AtomicU64::new(0),
+        }
+    }
+
+    pub fn schedule(
+        &'static self,
+        job_id: &str,
+        issuer: &str,
+        identity: String,
+        send_at: u64,
+        requests: Vec<Request>,
+    ) {
+        let delay = send_at_time(send_at)
+            .and_then(|at| at.duration_since(SystemTime::now()).ok())
+            .unwrap_or_default();
+
+        let entry = Entry {
+            job: ScheduledJob {
+                job_id: job_id.to_owned(),
+                issuer: issuer.to_owned(),
+                identity: identity.clone(),
+                send_at,
+                targets: requests.len(),
+            },
+            requests,
+        };
+
+        let key = self.next_key.fetch_add(1, Ordering::Relaxed);
+        self.entries.lock().unwrap().insert(key, entry);
+
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+
+            // Gone if cancelled meanwhile.
+            let Some(entry) = self.entries.lock().unwrap().remove(&key) else {
+                return;
+            };
+
+            tracing::info!(
+                "{identity} Scheduled time reached. Queuing {} targets",
+                entry.job.targets
+            );
+
+            for request in entry.requests {
+                self.sender
+                    .send(request)
+                    .await
+                    .expect("Failed to send Request");
+            }
+        });
+    }
+
+    /// Scheduled jobs of `issuer`, or of everyone for `None`.
+    pub fn list(&self, issuer: Option<&str>) -> Vec<ScheduledJob> {
+        let mut jobs: Vec<_> = self
+            .entries
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|entry| issuer.is_none_or(|issuer| entry.job.issuer == issuer))
+            .map(|entry| entry.job.clone())
+            .collect();
+
+        jobs.sort_by_key(|job| job.send_at);
+        jobs
+    }
+
+    /// Drops every scheduled request of the job and returns how many there were.
+    /// Only jobs of `issuer` are touched, or of anyone for `None`.
+    pub fn cancel(&self, job_id: &str, issuer: Option<&str>) -> usize {
+        let entries: Vec<_> = {
+            let mut entries = self.entries.lock().unwrap();
+            let keys: Vec<_> = entries
+                .iter()
+                .filter(|(_, entry)| entry.job.job_id == job_id)
+                .filter(|(_, entry)| issuer.is_none_or(|issuer| entry.job.issuer == issuer))
+                .map(|(key, _)| *key)
+                .collect();
+
+            keys.iter().filter_map(|key| entries.remove(key)).collect()
+        };
+
+        entries
+            .into_iter()
+            .flat_map(|entry| entry.requests)
+            .inspect(|request| request.finish(Outcome::Cancelled))
+            .count()
+    }
+}
@@ -0,0 +1,45 @@
+use std::collections::BTreeMap;
+
+use serde_json::Value;
+
+pub type Variables = BTreeMap<String, String>;
+
+/// Replaces `{{ name }}` in every string of `value`. Unknown names are left as is.
+pub fn render(value: &Value, vars: &Variables) -> Value {
+    match value {
+        Value::String(s) => Value::String(render_str(s, vars)),
+        Value::Array(values) => Value::Array(values.iter().map(|v| render(v, vars)).collect()),
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(k, v)| (k.clone(), render(v, vars)))
+                .collect(),
+        ),
+        value => value.clone(),
+    }
+}
+
+fn render_str(s: &str, vars: &Variables) -> String {
+    let mut rendered = String::with_capacity(s.len());
+    let mut rest = s;
+
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start + 2..].find("}}") else {
+            break;
+        };
+
+        let end = start + 2 + len + 2;
+        let name = rest[start + 2..end - 2].trim();
+
+        rendered.push_str(&rest[..start]);
+
+        match vars.get(name) {
+            Some(value) => rendered.push_str(value),
+            None => rendered.push_str(&rest[start..end]),
+        }
+
+        rest = &rest[end..];
+    }
+
+    rendered.push_str(rest);
+    rendered
+}
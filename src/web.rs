@@ -1,58 +1,241 @@
-use std::net::SocketAddr;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::Infallible;
 use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::time::Duration;
 
 use anyhow::{Context as _, Result as AHResult};
 use axum::{
     Router,
-    extract::{Json, State},
+    extract::{DefaultBodyLimit, FromRef, Json, Path, Query, State},
     http::{
-        StatusCode,
+        HeaderMap, StatusCode,
         header::{CONTENT_TYPE, HeaderValue},
     },
-    response::{IntoResponse, Response},
-    routing::{get, post},
+    middleware,
+    response::{
+        IntoResponse, Response,
+        sse::{self, KeepAlive, Sse},
+    },
+    routing::{delete, get, post},
 };
-use axum_extra::TypedHeader;
+use base64::prelude::{BASE64_STANDARD, Engine as _};
 use bytes::Bytes;
-use serde::Deserialize;
-use tokio::net::TcpListener;
+use futures_util::stream::{self, Stream};
+use serde::{Deserialize, Deserializer, Serialize};
+use tokio::sync::broadcast::error::RecvError;
 
+use crate::auth::{
+    self, AdminScope, Authorized, NotfoundsDeleteScope, NotfoundsReadScope, SendScope, Tokens,
+};
+use crate::callback::{Callback, Notifier};
+use crate::dedup::{Deduplicator, Destination};
+use crate::discord::{InvalidField, InvalidWebhook, WebhookId};
+use crate::idempotency::IdempotencyKeys;
+use crate::jobs::Jobs;
 use crate::limiter::Limiter;
-use crate::request::{Context, JobSender, Request};
+use crate::listener::{ApiListener, Peer};
+use crate::multipart::{Attachment, Multipart};
+use crate::request::{Context, JobSender, Outcomes, Request};
+use crate::schedule::Scheduler;
+use crate::template::Variables;
 
 #[derive(Clone, Debug)]
 struct AppState {
     sender: JobSender,
+    retry_sender: JobSender,
     limiter: &'static Limiter,
-    auth_token: String,
+    dedup: Option<&'static Deduplicator>,
+    idempotency: &'static IdempotencyKeys,
+    notifier: Option<&'static Notifier>,
+    jobs: &'static Jobs,
+    scheduler: &'static Scheduler,
+    tokens: &'static Tokens,
+    readiness: Readiness,
+}
+
+/// What the API server is wired to.
+#[derive(Debug)]
+pub struct Config {
+    pub sender: JobSender,
+    /// Only read for its queue depth.
+    pub retry_sender: JobSender,
+    pub limiter: &'static Limiter,
+    pub dedup_window: Option<Duration>,
+    pub idempotency_ttl: Duration,
+    pub notifier: Option<&'static Notifier>,
+    pub tokens: &'static Tokens,
+    pub readiness: Readiness,
+}
+
+/// Thresholds `/readyz` checks before reporting the instance ready for jobs.
+#[derive(Clone, Copy, Debug)]
+pub struct Readiness {
+    pub min_connections: usize,
+    pub max_queue_depth: usize,
+}
+
+impl FromRef<AppState> for &'static Tokens {
+    fn from_ref(app: &AppState) -> Self {
+        app.tokens
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+enum TargetSpec {
+    Url(url::Url),
+    Detailed {
+        url: url::Url,
+        thread_id: Option<u64>,
+        thread_name: Option<String>,
+        #[serde(default)]
+        query: BTreeMap<String, String>,
+        #[serde(default)]
+        vars: Variables,
+    },
+}
+
+/// A target with `thread_id` and query overrides already folded into `url`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(from = "TargetSpec")]
+struct WebTarget {
+    url: url::Url,
+    thread_name: Option<String>,
+    vars: Variables,
+}
+
+impl From<TargetSpec> for WebTarget {
+    fn from(spec: TargetSpec) -> Self {
+        let (mut url, thread_id, thread_name, mut query, vars) = match spec {
+            TargetSpec::Url(url) => (url, None, None, BTreeMap::new(), Variables::new()),
+            TargetSpec::Detailed {
+                url,
+                thread_id,
+                thread_name,
+                query,
+                vars,
+            } => (url, thread_id, thread_name, query, vars),
+        };
+
+        if let Some(thread_id) = thread_id {
+            query.insert("thread_id".to_string(), thread_id.to_string());
+        }
+
+        if !query.is_empty() {
+            let mut pairs: Vec<(String, String)> = url
+                .query_pairs()
+                .filter(|(k, _)| !query.contains_key(k.as_ref()))
+                .map(|(k, v)| (k.into_owned(), v.into_owned()))
+                .collect();
+
+            pairs.extend(query);
+
+            url.query_pairs_mut().clear().extend_pairs(pairs);
+        }
+
+        Self {
+            url,
+            thread_name,
+            vars,
+        }
+    }
+}
+
+fn from_base64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Bytes, D::Error> {
+    let encoded = String::deserialize(deserializer)?;
+
+    BASE64_STANDARD
+        .decode(encoded)
+        .map(Bytes::from)
+        .map_err(serde::de::Error::custom)
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct WebAttachment {
+    filename: String,
+    content_type: Option<String>,
+    #[serde(deserialize_with = "from_base64")]
+    data: Bytes,
+}
+
+impl From<WebAttachment> for Attachment {
+    fn from(attachment: WebAttachment) -> Self {
+        Self {
+            filename: attachment.filename,
+            content_type: attachment
+                .content_type
+                .unwrap_or_else(|| "application/octet-stream".to_string()),
+            data: attachment.data,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Deserialize)]
 struct WebRequest {
-    targets: Vec<url::Url>,
+    targets: Vec<WebTarget>,
     body: serde_json::Value,
     retry_limit: Option<usize>,
+    /// Render `{{ name }}` placeholders in `body` with each target's `vars`.
+    #[serde(default)]
+    template: bool,
+    /// Sent as `multipart/form-data` with `body` as `payload_json` when not empty.
+    #[serde(default)]
+    attachments: Vec<WebAttachment>,
+    /// Receives a signed summary once every target is settled.
+    callback_url: Option<url::Url>,
+    /// Also post an event to `callback_url` as each target is settled.
+    #[serde(default)]
+    callback_events: bool,
+    /// Hold the request until this UNIX time (in seconds).
+    send_at: Option<u64>,
 }
 
-async fn get_notfounds(
-    State(app): State<AppState>,
-    TypedHeader(token): TypedHeader<headers::Authorization<headers::authorization::Bearer>>,
-) -> Response {
-    if token.0.token() != app.auth_token {
-        return (StatusCode::UNAUTHORIZED, "UNAUTHORIZED").into_response();
-    }
+#[derive(Clone, Debug, Serialize)]
+struct Queued {
+    job_id: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct InvalidTarget {
+    url: url::Url,
+    reason: InvalidWebhook,
+}
 
+#[derive(Clone, Debug, Serialize)]
+struct InvalidTargets {
+    error: &'static str,
+    targets: Vec<InvalidTarget>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct InvalidBody {
+    request: usize,
+    /// The first target the invalid rendering was for, `None` for the body as given.
+    target: Option<usize>,
+    #[serde(flatten)]
+    field: InvalidField,
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct InvalidBodies {
+    error: &'static str,
+    fields: Vec<InvalidBody>,
+}
+
+async fn get_notfounds(State(app): State<AppState>, _: Authorized<NotfoundsReadScope>) -> Response {
     Json(app.limiter.notfounds()).into_response()
 }
 
 async fn delete_notfounds(
     State(app): State<AppState>,
-    TypedHeader(token): TypedHeader<headers::Authorization<headers::authorization::Bearer>>,
+    _: Authorized<NotfoundsDeleteScope>,
     Json(targets): Json<Vec<url::Url>>,
 ) -> Response {
-    if token.0.token() != app.auth_token {
-        return (StatusCode::UNAUTHORIZED, "UNAUTHORIZED").into_response();
-    }
+    let targets: Vec<_> = targets
+        .iter()
+        .filter_map(|target| WebhookId::parse(target).ok())
+        .collect();
 
     tokio::spawn(async move {
         tracing::info!("Clear {} 404 targets scheduled after 60(s)!", targets.len());
@@ -63,49 +246,323 @@ async fn delete_notfounds(
     "OK".into_response()
 }
 
+/// Builds a per-target body. Forum channels take the new thread's name here, not in the URL.
+fn render_body(
+    body: &serde_json::Value,
+    vars: Option<&Variables>,
+    thread_name: Option<&str>,
+) -> serde_json::Value {
+    let mut body = match vars {
+        Some(vars) => crate::template::render(body, vars),
+        None => body.clone(),
+    };
+
+    if let Some(name) = thread_name
+        && let Some(object) = body.as_object_mut()
+    {
+        object.insert("thread_name".to_string(), name.into());
+    }
+
+    body
+}
+
 #[axum::debug_handler]
 async fn send(
     State(app): State<AppState>,
-    TypedHeader(token): TypedHeader<headers::Authorization<headers::authorization::Bearer>>,
+    Authorized { token: issuer, .. }: Authorized<SendScope>,
+    headers: HeaderMap,
     Json(requests): Json<Vec<WebRequest>>,
 ) -> Response {
-    if token.0.token() != app.auth_token {
-        return (StatusCode::UNAUTHORIZED, "UNAUTHORIZED").into_response();
+    let invalid_targets: Vec<_> = requests
+        .iter()
+        .flat_map(|request| &request.targets)
+        .map(|target| &target.url)
+        .filter_map(|url| {
+            WebhookId::parse(url).err().map(|reason| InvalidTarget {
+                url: url.clone(),
+                reason,
+            })
+        })
+        .collect();
+
+    if !invalid_targets.is_empty() {
+        tracing::warn!(
+            "Rejected job with {} invalid targets",
+            invalid_targets.len()
+        );
+
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(InvalidTargets {
+                error: "INVALID_TARGETS",
+                targets: invalid_targets,
+            }),
+        )
+            .into_response();
+    }
+
+    // What Discord receives is each distinct rendering, so that is what gets validated.
+    let mut invalid_bodies = vec![];
+    let mut renderings = vec![];
+
+    for (i, request) in requests.iter().enumerate() {
+        let has_attachments = !request.attachments.is_empty();
+        let mut rendered = HashMap::new();
+        let mut sends_raw = false;
+
+        for (j, target) in request.targets.iter().enumerate() {
+            let vars = request.template.then_some(&target.vars);
+            let thread_name = target.thread_name.as_deref();
+
+            if vars.is_none() && thread_name.is_none() {
+                sends_raw = true;
+                continue;
+            }
+
+            let key = (target.thread_name.clone(), vars.cloned());
+
+            if rendered.contains_key(&key) {
+                continue;
+            }
+
+            let body = render_body(&request.body, vars, thread_name);
+
+            if let Err(fields) = crate::discord::validate_message(&body, has_attachments) {
+                invalid_bodies.extend(fields.into_iter().map(|field| InvalidBody {
+                    request: i,
+                    target: Some(j),
+                    field,
+                }));
+            }
+
+            rendered.insert(key, body);
+        }
+
+        if sends_raw
+            && let Err(fields) = crate::discord::validate_message(&request.body, has_attachments)
+        {
+            invalid_bodies.extend(fields.into_iter().map(|field| InvalidBody {
+                request: i,
+                target: None,
+                field,
+            }));
+        }
+
+        renderings.push(rendered);
     }
 
+    if !invalid_bodies.is_empty() {
+        tracing::warn!(
+            "Rejected job with {} invalid body fields",
+            invalid_bodies.len()
+        );
+
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(InvalidBodies {
+                error: "INVALID_BODY",
+                fields: invalid_bodies,
+            }),
+        )
+            .into_response();
+    }
+
+    let invalid_callback = requests
+        .iter()
+        .filter_map(|request| request.callback_url.as_ref())
+        .any(|url| {
+            app.notifier.is_none()
+                || !matches!(url.scheme(), "http" | "https")
+                || !crate::callback::is_allowed(url)
+        });
+
+    if invalid_callback {
+        tracing::warn!("Rejected job with invalid callback_url");
+
+        return (
+            StatusCode::BAD_REQUEST,
+            "INVALID_CALLBACK: callback_url must be a public http(s) URL and callbacks must be enabled",
+        )
+            .into_response();
+    }
+
+    let invalid_send_at = requests
+        .iter()
+        .filter_map(|request| request.send_at)
+        .any(|send_at| crate::schedule::send_at_time(send_at).is_none());
+
+    if invalid_send_at {
+        tracing::warn!("Rejected job with out of range send_at");
+
+        return (
+            StatusCode::BAD_REQUEST,
+            "INVALID_SEND_AT: send_at is out of range",
+        )
+            .into_response();
+    }
+
+    let queuing_id = crate::namesgenerator::generate(&mut rand::rng());
+    // Generated names can collide, so they only name the job in logs.
+    let job_id = format!("{:032x}", rand::random::<u128>());
+
+    let idempotency_key = headers
+        .get("idempotency-key")
+        .and_then(|key| key.to_str().ok());
+
+    if let Some(key) = idempotency_key
+        && let Err(existing) = app.idempotency.claim(&issuer.name, key, &job_id)
+    {
+        tracing::info!("{existing} Idempotency-Key replayed. Not queued again.");
+        return Json(Queued { job_id: existing }).into_response();
+    }
+
+    let total_targets = requests.iter().map(|request| request.targets.len()).sum();
+
+    if let Err(denied) = issuer.consume(total_targets) {
+        tracing::warn!("{queuing_id} {} exceeded its quota. Rejected.", issuer.name);
+
+        if let Some(key) = idempotency_key {
+            app.idempotency.release(&issuer.name, key);
+        }
+
+        return denied.into_response();
+    }
+
+    tracing::info!("{queuing_id} Issued by {} as job {job_id}", issuer.name);
+
     let my_requests = {
         let mut rng = rand::rng();
 
-        let queuing_id = crate::namesgenerator::generate(&mut rng);
-
         let mut my_requests = vec![];
+        let mut scheduled = vec![];
+        let mut contexts = vec![];
 
-        for request in requests {
+        for (request, rendered) in requests.into_iter().zip(renderings) {
             let request_id = crate::namesgenerator::generate(&mut rng);
+
+            let multipart = (!request.attachments.is_empty()).then(|| {
+                let attachments = request.attachments.into_iter().map(Into::into).collect();
+                Multipart::new(&mut rng, attachments)
+            });
+
+            let attachments = multipart.as_ref().map_or(&[][..], Multipart::attachments);
+
+            // Encodes the payload and hashes it, when deduplicating, as it was before encoding.
+            let encode = |payload_json: Bytes| {
+                let hash = app
+                    .dedup
+                    .map(|dedup| dedup.hash_body(&payload_json, attachments));
+
+                let body = match &multipart {
+                    Some(multipart) => multipart.encode(&payload_json),
+                    None => payload_json,
+                };
+
+                (body, hash)
+            };
+
+            let (body, body_hash) = encode(Bytes::from(request.body.to_string().into_bytes()));
+
+            let total = request.targets.len();
+            let mut destinations = HashSet::new();
+            let mut rendered_bodies = HashMap::new();
+            let mut targets = vec![];
+
+            for target in request.targets {
+                let webhook = WebhookId::parse(&target.url).expect("target is validated");
+                let thread_name = target.thread_name.as_deref();
+                let destination = Destination::new(&webhook, &target.url, thread_name);
+
+                if !destinations.insert(destination.clone()) {
+                    continue;
+                }
+
+                let vars = request.template.then_some(&target.vars);
+
+                let body_override = (vars.is_some() || thread_name.is_some()).then(|| {
+                    let key = (thread_name.map(str::to_owned), vars.cloned());
+
+                    rendered_bodies
+                        .entry(key.clone())
+                        .or_insert_with(|| encode(Bytes::from(rendered[&key].to_string())))
+                        .clone()
+                });
+
+                let hash = match &body_override {
+                    Some((_, hash)) => *hash,
+                    None => body_hash,
+                };
+
+                if let Some(dedup) = app.dedup
+                    && !dedup.admit(hash.unwrap(), destination)
+                {
+                    continue;
+                }
+
+                let body_override = body_override.map(|(body, _)| body);
+                targets.push((target.url, webhook, body_override));
+            }
+
             tracing::info!(
-                "{queuing_id}#{request_id} Queuing {} targets",
-                request.targets.len()
+                "{queuing_id}#{request_id} Queuing {} targets ({} duplicates skipped)",
+                targets.len(),
+                total - targets.len(),
             );
 
-            let body = Bytes::from(request.body.to_string().into_bytes());
+            let content_type = match &multipart {
+                Some(multipart) => multipart.content_type(),
+                None => HeaderValue::from_static("application/json"),
+            };
+
+            let callback = request.callback_url.map(|url| Callback {
+                url,
+                events: request.callback_events,
+                notifier: app.notifier.unwrap(),
+            });
+
             let context = Arc::new(Context {
                 identity: format!("{queuing_id}#{request_id}"),
                 body,
+                content_type,
+                job_id: job_id.clone(),
+                issuer: issuer.name.clone(),
+                outcomes: Outcomes::default(),
+                callback,
+                cancelled: AtomicBool::new(false),
                 retry_limit: request.retry_limit.unwrap_or(10),
             });
 
-            for target in request.targets {
+            contexts.push(context.clone());
+
+            let mut batch = vec![];
+
+            for (target, webhook, body) in targets {
                 let target_id = crate::namesgenerator::generate(&mut rng);
 
-                my_requests.push(Request {
+                batch.push(Request {
                     context: context.clone(),
                     retry_count: 0,
                     target,
+                    webhook,
+                    body,
                     identity: format!("{queuing_id}#{request_id}#{target_id}"),
                 });
             }
+
+            match request.send_at {
+                Some(send_at) => scheduled.push((context.identity.clone(), send_at, batch)),
+                None => my_requests.extend(batch),
+            }
+        }
+
+        for (identity, send_at, batch) in scheduled {
+            tracing::info!("{identity} Scheduled at {send_at}");
+            app.scheduler
+                .schedule(&job_id, &issuer.name, identity, send_at, batch);
         }
 
+        app.jobs.register(&job_id, &issuer.name, &contexts);
+
         my_requests
     };
 
@@ -116,7 +573,74 @@ async fn send(
             .expect("Failed to send Request");
     }
 
-    "OK".into_response()
+    Json(Queued { job_id }).into_response()
+}
+
+async fn cancel_job(
+    State(app): State<AppState>,
+    Authorized { token, .. }: Authorized<SendScope>,
+    Path(job_id): Path<String>,
+) -> Response {
+    let cancelled = app.jobs.cancel(&job_id, token.owner());
+    app.scheduler.cancel(&job_id, token.owner());
+
+    match cancelled {
+        Some(cancelled) => {
+            tracing::info!(
+                "{job_id} Cancelled! ({} delivered, {} pending)",
+                cancelled.delivered,
+                cancelled.pending
+            );
+            Json(cancelled).into_response()
+        }
+        None => (StatusCode::NOT_FOUND, "NOT_FOUND").into_response(),
+    }
+}
+
+async fn get_connections(_: Authorized<AdminScope>) -> Response {
+    Json(crate::registry::snapshot()).into_response()
+}
+
+async fn get_scheduled(
+    State(app): State<AppState>,
+    Authorized { token, .. }: Authorized<SendScope>,
+) -> Response {
+    Json(app.scheduler.list(token.owner())).into_response()
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct EventsQuery {
+    job: Option<String>,
+}
+
+fn event_stream(job: Option<String>) -> impl Stream<Item = Result<sse::Event, Infallible>> {
+    let rx = crate::events::subscribe();
+
+    stream::unfold((rx, job), |(mut rx, job)| async move {
+        loop {
+            let event = match rx.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(skipped)) => {
+                    let comment = sse::Event::default().comment(format!("{skipped} events lagged"));
+                    return Some((Ok(comment), (rx, job)));
+                }
+                Err(RecvError::Closed) => return None,
+            };
+
+            if job.is_some() && event.job() != job.as_deref() {
+                continue;
+            }
+
+            let event = sse::Event::default().json_data(&*event).unwrap();
+            return Some((Ok(event), (rx, job)));
+        }
+    })
+}
+
+async fn events(_: Authorized<AdminScope>, Query(query): Query<EventsQuery>) -> Response {
+    Sse::new(event_stream(query.job))
+        .keep_alive(KeepAlive::default())
+        .into_response()
 }
 
 async fn root() -> Response {
@@ -130,32 +654,128 @@ async fn root() -> Response {
         .into_response()
 }
 
-pub async fn run(
-    listen: SocketAddr,
-    sender: JobSender,
-    limiter: &'static Limiter,
-    auth_token: &str,
-) -> AHResult<()> {
-    let auth_token = auth_token.to_owned();
+async fn healthz() -> &'static str {
+    "ok"
+}
 
-    let app = Router::new()
-        .route("/", get(root))
-        .route("/api/send", post(send))
+#[derive(Clone, Debug, Serialize)]
+struct ReadyStatus {
+    ready: bool,
+    connections: usize,
+    queue_depth: usize,
+}
+
+/// Discord's IPs are resolved before the API starts, so only the pool and queue are left to check.
+async fn readyz(State(app): State<AppState>) -> Response {
+    let connections = crate::registry::open();
+    let queue_depth = app.sender.len() + app.retry_sender.len();
+
+    let ready =
+        connections >= app.readiness.min_connections && queue_depth < app.readiness.max_queue_depth;
+
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (
+        status,
+        Json(ReadyStatus {
+            ready,
+            connections,
+            queue_depth,
+        }),
+    )
+        .into_response()
+}
+
+pub async fn run(listener: ApiListener, config: Config) -> AHResult<()> {
+    let Config {
+        sender,
+        retry_sender,
+        limiter,
+        dedup_window,
+        idempotency_ttl,
+        notifier,
+        tokens,
+        readiness,
+    } = config;
+
+    let dedup = dedup_window
+        .filter(|window| !window.is_zero())
+        .map(|window| &*Box::leak(Box::new(Deduplicator::new(window))));
+
+    if let Some(dedup) = dedup {
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(dedup.window()).await;
+                dedup.purge();
+            }
+        });
+    }
+
+    let idempotency = &*Box::leak(Box::new(IdempotencyKeys::new(idempotency_ttl)));
+
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(idempotency.ttl().max(Duration::from_secs(1))).await;
+            idempotency.purge();
+        }
+    });
+
+    let jobs = &*Box::leak(Box::new(Jobs::default()));
+    let scheduler = &*Box::leak(Box::new(Scheduler::new(sender.clone())));
+
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            jobs.purge();
+        }
+    });
+
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            tokens.purge();
+        }
+    });
+
+    // Only these accept HMAC-signed requests in place of a bearer token.
+    let signed = Router::new()
+        .route(
+            "/api/send",
+            post(send).layer(DefaultBodyLimit::max(auth::SEND_BODY_LIMIT)),
+        )
         .route(
             "/api/notfounds",
             get(get_notfounds).delete(delete_notfounds),
         )
+        .route_layer(middleware::from_fn_with_state(tokens, auth::verify_signed));
+
+    let app = Router::new()
+        .route("/", get(root))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .route("/api/events", get(events))
+        .route("/api/jobs/{id}", delete(cancel_job))
+        .route("/api/scheduled", get(get_scheduled))
+        .route("/api/admin/connections", get(get_connections))
+        .merge(signed)
         .with_state(AppState {
             sender,
+            retry_sender,
             limiter,
-            auth_token,
+            dedup,
+            idempotency,
+            notifier,
+            jobs,
+            scheduler,
+            tokens,
+            readiness,
         });
 
-    let listener = TcpListener::bind(listen)
-        .await
-        .context("Failed to bind address")?;
-
-    axum::serve(listener, app)
+    axum::serve(listener, app.into_make_service_with_connect_info::<Peer>())
         .await
         .context("Failed to serve HTTP contents")?;
 